@@ -9,14 +9,25 @@ pub mod routes;
 pub mod structs;
 
 use actix_web::{
-    web::{self, ServiceConfig},
+    web::{self, FormConfig, PathConfig, QueryConfig, ServiceConfig},
     App, HttpServer,
 };
 
 use log::{debug, error, info};
-use modules::{config::Config, storage::Storage};
-use mongodb::{options::ClientOptions, Client, Database};
-use routes::{api::v1::files::*, api::v1::users::*, views::index::*};
+use modules::{
+    compression,
+    config::Config,
+    database::{self, DatabaseBackend},
+    ids, passwords, roles,
+    storage::{self, StorageBackend},
+    sweeper,
+};
+use routes::{
+    api::v1::auth::*, api::v1::files::*, api::v1::invites::*, api::v1::roles::*, api::v1::stats::*,
+    api::v1::tokens::*, api::v1::users::*, api::v1::vault::*, error::ApiError, views::index::*,
+    views::viewer::*,
+};
+use std::sync::Arc;
 use tera::Tera;
 
 lazy_static::lazy_static! {
@@ -35,17 +46,57 @@ lazy_static::lazy_static! {
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
-    pub database: Database,
-    pub storage: Storage,
+    pub database: Arc<dyn DatabaseBackend>,
+    pub storage: Arc<dyn StorageBackend>,
     pub tera: Tera,
 }
 
 fn routes(cfg: &mut ServiceConfig) {
-    cfg.route("/", web::get().to(index))
-        .route("/api/v1/files", web::post().to(upload_file))
-        .route("/api/v1/files/delete", web::get().to(delete_file))
-        .route("/{hash}", web::get().to(get_file))
-        .route("/api/v1/users", web::post().to(create_user));
+    //? Malformed forms, queries and paths are reported like every other API error
+    cfg.app_data(
+        FormConfig::default().error_handler(|e, _| ApiError::bad_request(e.to_string()).into()),
+    )
+    .app_data(
+        QueryConfig::default().error_handler(|e, _| ApiError::bad_request(e.to_string()).into()),
+    )
+    .app_data(
+        PathConfig::default().error_handler(|e, _| ApiError::bad_request(e.to_string()).into()),
+    )
+    .route("/", web::get().to(index))
+    .route("/api/v1/files", web::post().to(upload_file))
+    .route("/api/v1/files/delete", web::get().to(delete_file))
+    .route("/{id}", web::get().to(get_file))
+    .route("/{id}", web::head().to(get_file))
+    .route("/{id}", web::post().to(unlock_file))
+    .route("/v/{id}", web::get().to(view_file))
+    .route("/api/v1/files/{id}/raw", web::get().to(get_raw_file))
+    .route("/api/v1/users", web::post().to(create_user))
+    .route("/api/v1/users", web::get().to(list_users))
+    .route("/api/v1/users/{id}", web::get().to(get_user))
+    .route("/api/v1/users/{id}", web::patch().to(update_user))
+    .route("/api/v1/users/{id}", web::delete().to(delete_user))
+    .route("/api/v1/users/{id}/suspend", web::post().to(suspend_user))
+    .route(
+        "/api/v1/users/{id}/unsuspend",
+        web::post().to(unsuspend_user),
+    )
+    .route("/api/v1/auth/login", web::post().to(login))
+    .route("/api/v1/auth/rotate", web::post().to(rotate_token))
+    .route("/api/v1/invites", web::get().to(list_invites))
+    .route("/api/v1/invites", web::post().to(create_invite))
+    .route("/api/v1/invites/{id}", web::delete().to(delete_invite))
+    .route("/api/v1/roles", web::get().to(list_roles))
+    .route("/api/v1/roles/{name}", web::put().to(put_role))
+    .route("/api/v1/roles/{name}", web::delete().to(delete_role))
+    .route("/api/v1/stats", web::get().to(get_stats))
+    .route("/api/v1/tokens", web::get().to(list_tokens))
+    .route("/api/v1/tokens", web::post().to(create_token))
+    .route("/api/v1/tokens/{id}", web::delete().to(revoke_token))
+    .route("/api/v1/vault", web::get().to(get_vault))
+    .route("/api/v1/vault", web::post().to(create_vault))
+    .route("/api/v1/vault", web::delete().to(delete_vault))
+    .route("/api/v1/vault/files", web::get().to(list_vault_files))
+    .route("/api/v1/vault/files", web::post().to(unlock_vault_files));
 }
 
 #[tokio::main]
@@ -54,39 +105,86 @@ async fn main() {
     let _config = Config::get_or_create("config.toml").unwrap();
     let config = _config.clone(); //TODO: remove the need for cloning the config struct
 
-    let client_options = match ClientOptions::parse(&config.database.uri).await {
-        Ok(opt) => {
-            debug!("Connecting to database...");
-            opt
+    if let Err(e) = ids::validate_alphabet(&config.files.id_alphabet) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    if config.files.id_length == 0 {
+        error!("The file ID length must be at least 1");
+        std::process::exit(1);
+    }
+
+    if config.files.sweep_interval == 0
+        || matches!(config.files.default_lifetime, Some(l) if l <= 0)
+    {
+        error!("The sweep interval and default file lifetime must be positive");
+        std::process::exit(1);
+    }
+
+    if matches!(config.files.default_lifetime, Some(l) if l > MAX_LIFETIME) {
+        error!(
+            "The default file lifetime must be at most {} seconds",
+            MAX_LIFETIME
+        );
+        std::process::exit(1);
+    }
+
+    if matches!(config.compression.level, Some(level) if !compression::validate_level(config.compression.algorithm, level))
+    {
+        error!("The compression level is not supported by the compression algorithm");
+        std::process::exit(1);
+    }
+
+    if passwords::validate(&config.passwords).is_err() {
+        error!("Invalid Argon2 password hashing parameters");
+        std::process::exit(1);
+    }
+
+    if config.passwords.min_length > config.passwords.max_length || config.passwords.min_classes > 4
+    {
+        error!("Invalid password policy");
+        std::process::exit(1);
+    }
+
+    debug!("Connecting to database...");
+
+    let database = match database::from_config(&config.database).await {
+        Ok(database) => {
+            info!("Using {} database", database.name());
+            database
         }
-        Err(_) => {
-            error!("Failed to parse MongoDB URI");
+        Err(e) => {
+            error!("Failed to connect to the database: {}", e);
             std::process::exit(1);
         }
     };
 
-    let client = match Client::with_options(client_options) {
-        Ok(client) => {
-            info!("Connection established to MongoDB");
-            client
+    if let Err(e) = database.setup().await {
+        error!(
+            "Failed to set up the database, check for duplicate users: {}",
+            e
+        );
+        std::process::exit(1);
+    }
+
+    if roles::setup(database.as_ref()).await.is_err() {
+        error!("Failed to set up the user roles");
+        std::process::exit(1);
+    }
+
+    let storage = match storage::from_config(&config.storage) {
+        Ok(storage) => {
+            info!("Using {} storage module", storage.name());
+            storage
         }
         Err(_) => {
-            error!("Failed to connect to MongoDB");
+            error!("Failed to configure the storage module");
             std::process::exit(1);
         }
     };
 
-    let database = client.database(&config.database.db_name);
-
-    //? This is very hacky, but it works for now.
-    let storage;
-    if config.storage.local.enabled {
-        info!("Using local storage module");
-        storage = Storage::Local(config.storage.local.path.clone());
-    } else {
-        info!("Using S3 storage module");
-        storage = Storage::S3(config.storage.s3.clone());
-    }
+    sweeper::spawn(database.clone(), storage.clone(), config.files.clone());
 
     let state = AppState {
         config,
@@ -0,0 +1,197 @@
+//! Compression of uploads before they are encrypted.
+//!
+//! Encrypted data does not compress, so compressible files are compressed on their way to the
+//! [`Encryptor`](super::crypto::Encryptor). The algorithm is recorded in the container header
+//! and on the file record, downloads are decompressed again or, when the client accepts the
+//! encoding, sent compressed with a `Content-Encoding` header.
+
+use std::io::{Error, ErrorKind, Write};
+
+use brotli::{CompressorWriter, DecompressorWriter};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use zstd::stream::write::{Decoder as ZstdDecoder, Encoder as ZstdEncoder};
+
+use super::{
+    container::Compression,
+    storage::{ByteStream, StorageError},
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Brotli window size as a power of two, the largest the format allows without extensions.
+const BROTLI_WINDOW: u32 = 22;
+
+/// The level used when none is configured, favouring upload speed over ratio.
+pub fn default_level(compression: Compression) -> i32 {
+    match compression {
+        Compression::None => 0,
+        Compression::Zstd => 3,
+        Compression::Brotli => 5,
+    }
+}
+
+/// Whether `level` is supported by the algorithm.
+pub fn validate_level(compression: Compression, level: i32) -> bool {
+    match compression {
+        Compression::None => true,
+        Compression::Zstd => zstd::compression_level_range().contains(&level),
+        Compression::Brotli => (0..=11).contains(&level),
+    }
+}
+
+/// Compresses data fed to it piece by piece.
+pub enum Compressor {
+    Zstd(ZstdEncoder<'static, Vec<u8>>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+}
+
+impl Compressor {
+    /// Creates a compressor, `None` for [`Compression::None`].
+    pub fn new(compression: Compression, level: i32) -> std::io::Result<Option<Compressor>> {
+        Ok(match compression {
+            Compression::None => None,
+            Compression::Zstd => Some(Compressor::Zstd(ZstdEncoder::new(Vec::new(), level)?)),
+            Compression::Brotli => Some(Compressor::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                BUFFER_SIZE,
+                level as u32,
+                BROTLI_WINDOW,
+            )))),
+        })
+    }
+
+    /// Compresses `data`, returning whatever compressed output is ready so far.
+    pub fn update(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        let out = match self {
+            Compressor::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Compressor::Brotli(writer) => {
+                writer.write_all(data)?;
+                writer.get_mut()
+            }
+        };
+
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    /// Ends the compressed stream and returns the rest of it.
+    pub fn finish(self) -> std::io::Result<Bytes> {
+        match self {
+            Compressor::Zstd(encoder) => Ok(Bytes::from(encoder.finish()?)),
+            Compressor::Brotli(writer) => Ok(Bytes::from(writer.into_inner())),
+        }
+    }
+}
+
+enum Decompressor {
+    Zstd(ZstdDecoder<'static, Vec<u8>>),
+    Brotli(Box<DecompressorWriter<Vec<u8>>>),
+}
+
+impl Decompressor {
+    fn update(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        let out = match self {
+            Decompressor::Zstd(decoder) => {
+                decoder.write_all(data)?;
+                decoder.flush()?;
+                decoder.get_mut()
+            }
+            Decompressor::Brotli(writer) => {
+                writer.write_all(data)?;
+                writer.get_mut()
+            }
+        };
+
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        match self {
+            Decompressor::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(Bytes::from(decoder.into_inner()))
+            }
+            Decompressor::Brotli(mut writer) => {
+                writer
+                    .close()
+                    .map_err(|_| Error::new(ErrorKind::InvalidData, "Truncated brotli stream"))?;
+                Ok(Bytes::from(std::mem::take(writer.get_mut())))
+            }
+        }
+    }
+}
+
+/// Decompresses a stream of bytes compressed with `compression` as they arrive.
+pub fn decompress_stream(compression: Compression, source: ByteStream) -> ByteStream {
+    let decompressor = match compression {
+        Compression::None => return source,
+        Compression::Zstd => match ZstdDecoder::new(Vec::new()) {
+            Ok(decoder) => Decompressor::Zstd(decoder),
+            Err(e) => return Box::pin(stream::once(async move { Err(e.into()) })),
+        },
+        Compression::Brotli => {
+            Decompressor::Brotli(Box::new(DecompressorWriter::new(Vec::new(), BUFFER_SIZE)))
+        }
+    };
+
+    Box::pin(stream::try_unfold(
+        (source, Some(decompressor)),
+        |(mut source, decompressor)| async move {
+            let mut decompressor = match decompressor {
+                Some(decompressor) => decompressor,
+                None => return Ok::<_, StorageError>(None),
+            };
+
+            loop {
+                match source.next().await {
+                    Some(chunk) => {
+                        let out = decompressor.update(&chunk?)?;
+
+                        if !out.is_empty() {
+                            return Ok(Some((out, (source, Some(decompressor)))));
+                        }
+                    }
+                    None => return Ok(Some((decompressor.finish()?, (source, None)))),
+                }
+            }
+        },
+    ))
+}
+
+#[tokio::test]
+async fn test_compression() {
+    use futures_util::TryStreamExt;
+
+    let data = "All work and no play makes Jack a dull boy.\n".repeat(10_000);
+
+    for compression in [Compression::Zstd, Compression::Brotli] {
+        let mut compressor = Compressor::new(compression, default_level(compression))
+            .unwrap()
+            .unwrap();
+
+        let mut compressed = Vec::new();
+
+        for piece in data.as_bytes().chunks(7_777) {
+            compressed.extend_from_slice(&compressor.update(piece).unwrap());
+        }
+
+        compressed.extend_from_slice(&compressor.finish().unwrap());
+        assert!(compressed.len() < data.len() / 10);
+
+        let pieces: Vec<Result<Bytes, StorageError>> = compressed
+            .chunks(1_000)
+            .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+            .collect();
+
+        let decompressed: Vec<Bytes> =
+            decompress_stream(compression, Box::pin(stream::iter(pieces)))
+                .try_collect()
+                .await
+                .unwrap();
+
+        assert_eq!(decompressed.concat(), data.as_bytes());
+    }
+}
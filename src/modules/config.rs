@@ -3,10 +3,25 @@ use std::io::Read;
 use std::io::Write;
 use toml;
 
+use super::{container::Compression, ids::DEFAULT_ALPHABET, slugs::Slug};
+
+/// Which database records are kept in, see `modules::database`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseKind {
+    #[default]
+    Mongodb,
+    Postgres,
+    Sqlite,
+}
+
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct DatabaseConfig {
-    pub uri: String,
-    pub db_name: String,
+    #[serde(default)]
+    pub kind: DatabaseKind,
+    pub uri: String, //? e.g. `postgres://user@localhost/mgo` or `sqlite://mgo.db?mode=rwc`
+    #[serde(default)]
+    pub db_name: String, //? Only used by MongoDB
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
@@ -29,6 +44,8 @@ pub struct S3StorageConfig {
     pub region: String,
     pub access_key: String,
     pub secret_key: String,
+    #[serde(default)]
+    pub path_style: bool, //? Required by MinIO and most self-hosted S3 servers
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
@@ -37,11 +54,177 @@ pub struct StorageConfig {
     pub s3: S3StorageConfig,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FilesConfig {
+    pub id_length: usize,
+    pub id_alphabet: String,
+    #[serde(default)]
+    pub default_lifetime: Option<i64>, //? Seconds, files never expire by default
+    #[serde(default = "FilesConfig::default_sweep_interval")]
+    pub sweep_interval: u64, //? Seconds between removals of expired files
+    #[serde(default = "FilesConfig::default_expired_retention")]
+    pub expired_retention: i64, //? Seconds an expired link keeps answering 410 Gone
+    #[serde(default)]
+    pub encrypt_metadata: bool, //? Store filenames and MIME types encrypted with the file key
+    #[serde(default)]
+    pub slug: Slug, //? How links spell file IDs unless the user or upload picks otherwise
+}
+
+impl FilesConfig {
+    fn default_sweep_interval() -> u64 {
+        60
+    }
+
+    fn default_expired_retention() -> i64 {
+        60 * 60 * 24 * 30
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            id_length: 8,
+            id_alphabet: String::from(DEFAULT_ALPHABET),
+            default_lifetime: None,
+            sweep_interval: FilesConfig::default_sweep_interval(),
+            expired_retention: FilesConfig::default_expired_retention(),
+            encrypt_metadata: false,
+            slug: Slug::Random,
+        }
+    }
+}
+
+/// Compression applied to uploads before they are encrypted, off by default.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CompressionConfig {
+    pub algorithm: Compression, //? none, zstd or brotli
+    #[serde(default)]
+    pub level: Option<i32>, //? Defaults to a fast level of the algorithm
+    #[serde(default = "CompressionConfig::default_mimetypes")]
+    pub mimetypes: Vec<String>, //? Prefixes of the MIME types worth compressing
+}
+
+impl CompressionConfig {
+    fn default_mimetypes() -> Vec<String> {
+        [
+            "text/",
+            "application/json",
+            "application/javascript",
+            "application/xml",
+            "application/x-ndjson",
+            "application/x-yaml",
+            "application/toml",
+            "application/sql",
+            "image/svg+xml",
+            "image/bmp",
+        ]
+        .iter()
+        .map(|mimetype| mimetype.to_string())
+        .collect()
+    }
+
+    /// The compression to use for an upload of the given MIME type.
+    pub fn for_mimetype(&self, mimetype: &str) -> Compression {
+        match self
+            .mimetypes
+            .iter()
+            .any(|prefix| mimetype.starts_with(prefix.as_str()))
+        {
+            true => self.algorithm,
+            false => Compression::None,
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            algorithm: Compression::None,
+            level: None,
+            mimetypes: CompressionConfig::default_mimetypes(),
+        }
+    }
+}
+
+/// Argon2id cost parameters and the password policy, the defaults follow the OWASP recommendations.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PasswordConfig {
+    pub memory_cost: u32, //? KiB
+    pub time_cost: u32,
+    pub parallelism: u32,
+    #[serde(default = "PasswordConfig::default_min_length")]
+    pub min_length: usize,
+    #[serde(default = "PasswordConfig::default_max_length")]
+    pub max_length: usize,
+    #[serde(default = "PasswordConfig::default_min_classes")]
+    pub min_classes: usize, //? Of lowercase, uppercase, digits and symbols
+}
+
+impl PasswordConfig {
+    fn default_min_length() -> usize {
+        10
+    }
+
+    fn default_max_length() -> usize {
+        256
+    }
+
+    fn default_min_classes() -> usize {
+        2
+    }
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        PasswordConfig {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+            min_length: PasswordConfig::default_min_length(),
+            max_length: PasswordConfig::default_max_length(),
+            min_classes: PasswordConfig::default_min_classes(),
+        }
+    }
+}
+
+/// Who may create an account through `POST /api/v1/users`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    Closed,
+    Invite,
+    Open,
+}
+
+/// The very first account can always be registered and becomes an administrator.
+///
+/// Registration stays open by default, as it was before invites existed.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegistrationConfig {
+    pub mode: RegistrationMode,
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        RegistrationConfig {
+            mode: RegistrationMode::Open,
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
     pub storage: StorageConfig,
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub files: FilesConfig,
+    #[serde(default)]
+    pub passwords: PasswordConfig,
+    #[serde(default)]
+    pub registration: RegistrationConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
 }
 
 impl Config {
@@ -50,6 +233,7 @@ impl Config {
             server,
             storage,
             database,
+            ..Config::default()
         }
     }
 
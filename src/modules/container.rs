@@ -0,0 +1,509 @@
+//! The `.mgo` container format.
+//!
+//! Every file written by the server starts with a small, self-describing header followed
+//! by the chunks produced by [`Encryptor`]:
+//!
+//! | offset | size | field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 4    | magic bytes (`\x89MGO`)                        |
+//! | 4      | 1    | format version                                 |
+//! | 5      | 1    | cipher id                                      |
+//! | 6      | 1    | compression id, applied before encryption      |
+//! | 7      | 1    | reserved, always zero                          |
+//! | 8      | 4    | plaintext chunk size (big-endian)              |
+//! | 12     | 8    | plaintext length, `u64::MAX` if unknown (big-endian) |
+//!
+//! Files written before the header existed are a single AES-256-GCM-SIV message with no
+//! header at all, they are recognised by the missing magic bytes and still decrypted.
+
+use bytes::{Bytes, BytesMut};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+
+use super::{
+    crypto::{decrypt_bytes, Decryptor, EncryptionKey, Encryptor, CHUNK_SIZE, TAG_SIZE},
+    storage::{slice_stream, ByteStream, StorageBackend, StorageError, StorageResult},
+};
+
+pub const MAGIC: [u8; 4] = *b"\x89MGO";
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 20;
+
+const UNKNOWN_LENGTH: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    /// AES-256-GCM-SIV sealed in chunks by [`Encryptor`].
+    Aes256GcmSivChunked = 1,
+}
+
+impl Cipher {
+    pub fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1 => Some(Cipher::Aes256GcmSivChunked),
+            _ => None,
+        }
+    }
+}
+
+/// How the plaintext was compressed before it was encrypted, see `modules::compression`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Zstd = 1,
+    Brotli = 2,
+}
+
+impl Compression {
+    pub fn from_id(id: u8) -> Option<Compression> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zstd),
+            2 => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    /// The `Content-Encoding` token of the algorithm.
+    pub fn encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd => Some("zstd"),
+            Compression::Brotli => Some("br"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub cipher: Cipher,
+    pub compression: Compression,
+    pub chunk_size: u32,
+    pub plaintext_len: Option<u64>,
+}
+
+impl Header {
+    pub fn new(chunk_size: u32, plaintext_len: Option<u64>) -> Header {
+        Header {
+            version: VERSION,
+            cipher: Cipher::Aes256GcmSivChunked,
+            compression: Compression::None,
+            chunk_size,
+            plaintext_len,
+        }
+    }
+
+    /// Records that the plaintext was compressed with `compression` before being encrypted.
+    pub fn compressed(self, compression: Compression) -> Header {
+        Header {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.cipher as u8;
+        bytes[6] = self.compression as u8;
+        bytes[8..12].copy_from_slice(&self.chunk_size.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.plaintext_len.unwrap_or(UNKNOWN_LENGTH).to_be_bytes());
+
+        bytes
+    }
+
+    /// Parses the header at the start of `data`.
+    ///
+    /// Returns `Ok(None)` for legacy headerless files and an error for headers that are
+    /// truncated or were written by an unsupported version of the format.
+    pub fn parse(data: &[u8]) -> std::io::Result<Option<Header>> {
+        if data.len() < MAGIC.len() || data[0..4] != MAGIC {
+            return Ok(None);
+        }
+
+        if data.len() < HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "Truncated file header"));
+        }
+
+        if data[4] != VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported file format version {}", data[4]),
+            ));
+        }
+
+        let cipher = Cipher::from_id(data[5])
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unsupported cipher"))?;
+        let compression = Compression::from_id(data[6])
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unsupported compression"))?;
+
+        let chunk_size = u32::from_be_bytes(data[8..12].try_into().unwrap());
+        let plaintext_len = u64::from_be_bytes(data[12..20].try_into().unwrap());
+
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid chunk size"));
+        }
+
+        Ok(Some(Header {
+            version: data[4],
+            cipher,
+            compression,
+            chunk_size,
+            plaintext_len: match plaintext_len {
+                UNKNOWN_LENGTH => None,
+                len => Some(len),
+            },
+        }))
+    }
+}
+
+/// Encrypts `data` into a complete `.mgo` container.
+pub fn seal(crypto: &EncryptionKey, data: &[u8]) -> std::io::Result<Bytes> {
+    let header = Header::new(CHUNK_SIZE as u32, Some(data.len() as u64));
+    let mut encryptor = Encryptor::with_chunk_size(crypto, CHUNK_SIZE)?;
+
+    let mut out = BytesMut::from(&header.to_bytes()[..]);
+    out.extend_from_slice(&encryptor.update(data)?);
+    out.extend_from_slice(&encryptor.finish()?);
+
+    Ok(out.freeze())
+}
+
+/// Decrypts a `.mgo` container, falling back to the legacy headerless format.
+pub fn open(crypto: &EncryptionKey, data: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>> {
+    let header = match Header::parse(data)? {
+        Some(header) => header,
+        None => return decrypt_bytes(crypto, data),
+    };
+
+    let mut decryptor = Decryptor::with_chunk_size(crypto, header.chunk_size as usize)?;
+
+    let mut out = BytesMut::from(decryptor.update(&data[HEADER_SIZE..])?.as_ref());
+    out.extend_from_slice(&decryptor.finish()?);
+
+    if let Some(len) = header.plaintext_len {
+        if out.len() as u64 != len {
+            return Err(Box::new(Error::new(
+                ErrorKind::InvalidData,
+                "Decrypted length does not match the file header",
+            )));
+        }
+    }
+
+    Ok(out.freeze())
+}
+
+/// Incrementally decrypts a `.mgo` container as its bytes arrive.
+///
+/// Chunked files are decrypted one chunk at a time, legacy headerless files are a single
+/// message and have to be buffered completely before they can be authenticated.
+enum Reader {
+    Pending(BytesMut),
+    Chunked {
+        decryptor: Box<Decryptor>,
+        expected: Option<u64>,
+        decrypted: u64,
+        until: Option<u64>, //? Index after the last chunk of a range that stops before the final one
+    },
+    Legacy(BytesMut),
+    Done,
+}
+
+struct OpenState<S> {
+    source: S,
+    crypto: EncryptionKey,
+    reader: Reader,
+}
+
+impl<S> OpenState<S> {
+    fn feed(&mut self, data: &[u8]) -> StorageResult<Bytes> {
+        match self.reader {
+            Reader::Pending(ref mut buffer) => {
+                buffer.extend_from_slice(data);
+
+                if buffer.len() < HEADER_SIZE && buffer.starts_with(&MAGIC[..buffer.len().min(4)]) {
+                    return Ok(Bytes::new());
+                }
+
+                let buffer = buffer.split();
+
+                match Header::parse(&buffer)? {
+                    Some(header) => {
+                        let decryptor = Box::new(Decryptor::with_chunk_size(
+                            &self.crypto,
+                            header.chunk_size as usize,
+                        )?);
+
+                        self.reader = Reader::Chunked {
+                            decryptor,
+                            expected: header.plaintext_len,
+                            decrypted: 0,
+                            until: None,
+                        };
+
+                        self.feed(&buffer[HEADER_SIZE..])
+                    }
+                    None => {
+                        self.reader = Reader::Legacy(buffer);
+                        Ok(Bytes::new())
+                    }
+                }
+            }
+            Reader::Chunked {
+                ref mut decryptor,
+                ref mut decrypted,
+                ..
+            } => {
+                let out = decryptor.update(data)?;
+                *decrypted += out.len() as u64;
+                Ok(out)
+            }
+            Reader::Legacy(ref mut buffer) => {
+                buffer.extend_from_slice(data);
+                Ok(Bytes::new())
+            }
+            Reader::Done => Ok(Bytes::new()),
+        }
+    }
+
+    fn finish(&mut self) -> StorageResult<Bytes> {
+        match std::mem::replace(&mut self.reader, Reader::Done) {
+            Reader::Pending(buffer) | Reader::Legacy(buffer) => {
+                //? Anything shorter than a header that still looks like one is a truncated file
+                Header::parse(&buffer)?;
+
+                decrypt_bytes(&self.crypto, &buffer.freeze()).map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, "Failed to decrypt data").into()
+                })
+            }
+            Reader::Chunked {
+                decryptor,
+                until: Some(until),
+                ..
+            } => Ok(decryptor.finish_range(until)?),
+            Reader::Chunked {
+                decryptor,
+                expected,
+                decrypted,
+                ..
+            } => {
+                let out = decryptor.finish()?;
+
+                if let Some(len) = expected {
+                    if decrypted + out.len() as u64 != len {
+                        return Err(Box::new(Error::new(
+                            ErrorKind::InvalidData,
+                            "Decrypted length does not match the file header",
+                        )));
+                    }
+                }
+
+                Ok(out)
+            }
+            Reader::Done => Ok(Bytes::new()),
+        }
+    }
+}
+
+/// Decrypts a stream of container bytes, yielding plaintext as soon as each chunk is authenticated.
+pub fn open_stream<S>(crypto: EncryptionKey, source: S) -> impl Stream<Item = StorageResult<Bytes>>
+where
+    S: Stream<Item = StorageResult<Bytes>> + Unpin,
+{
+    decrypt(OpenState {
+        source,
+        crypto,
+        reader: Reader::Pending(BytesMut::new()),
+    })
+}
+
+fn decrypt<S>(state: OpenState<S>) -> impl Stream<Item = StorageResult<Bytes>>
+where
+    S: Stream<Item = StorageResult<Bytes>> + Unpin,
+{
+    stream::try_unfold(state, |mut state| async move {
+        loop {
+            if let Reader::Done = state.reader {
+                return Ok::<_, StorageError>(None);
+            }
+
+            let out = match state.source.next().await {
+                Some(chunk) => state.feed(&chunk?)?,
+                None => state.finish()?,
+            };
+
+            if !out.is_empty() {
+                return Ok(Some((out, state)));
+            }
+        }
+    })
+}
+
+/// Decrypts the inclusive plaintext range `start..=end` of a stored container holding `size`
+/// bytes of plaintext.
+///
+/// Only the chunks covering the range are fetched and decrypted, legacy headerless files have
+/// to be decrypted from the start.
+pub async fn open_range(
+    storage: &dyn StorageBackend,
+    key: &str,
+    crypto: EncryptionKey,
+    size: u64,
+    start: u64,
+    end: u64,
+) -> StorageResult<ByteStream> {
+    let mut head = BytesMut::new();
+    let mut source = storage.get_range(key, 0, HEADER_SIZE as u64 - 1).await?;
+
+    while let Some(chunk) = source.next().await {
+        head.extend_from_slice(&chunk?);
+    }
+
+    let header = match Header::parse(&head)? {
+        Some(header) => header,
+        None => {
+            let source = storage.get_stream(key).await?;
+            let stream = Box::pin(open_stream(crypto, source));
+            return Ok(slice_stream(stream, start, end - start + 1));
+        }
+    };
+
+    let chunk_size = header.chunk_size as u64;
+    let sealed_size = chunk_size + TAG_SIZE as u64;
+
+    //? Every file has at least one (possibly empty) chunk and only the final one may be short
+    let chunks = size.div_ceil(chunk_size).max(1);
+    let first = start / chunk_size;
+    let last = end / chunk_size;
+
+    let sealed_start = HEADER_SIZE as u64 + first * sealed_size;
+    let sealed_end =
+        HEADER_SIZE as u64 + ((last + 1) * sealed_size).min(size + chunks * TAG_SIZE as u64) - 1;
+
+    let mut decryptor = Decryptor::with_chunk_size(&crypto, chunk_size as usize)?;
+    decryptor.seek(first);
+
+    let source = storage.get_range(key, sealed_start, sealed_end).await?;
+    let stream = decrypt(OpenState {
+        source,
+        crypto,
+        reader: Reader::Chunked {
+            decryptor: Box::new(decryptor),
+            expected: None,
+            decrypted: 0,
+            until: (last + 1 < chunks).then_some(last + 1),
+        },
+    });
+
+    Ok(slice_stream(
+        Box::pin(stream),
+        start - first * chunk_size,
+        end - start + 1,
+    ))
+}
+
+#[test]
+fn test_container() {
+    use super::crypto::{encrypt_bytes, generate_key};
+
+    let crypto = generate_key();
+    let data = BytesMut::from("Hello World!");
+
+    let sealed = seal(&crypto, &data).unwrap();
+    let header = Header::parse(&sealed).unwrap().unwrap();
+
+    assert_eq!(header, Header::new(CHUNK_SIZE as u32, Some(12)));
+    assert_eq!(open(&crypto, &sealed).unwrap(), data);
+
+    //? Files written before the header was introduced
+    let legacy = encrypt_bytes(&crypto, &data).unwrap();
+    assert_eq!(open(&crypto, &legacy).unwrap(), data);
+
+    //? Keys and nonces of the wrong length come from links and must not panic
+    let short_key = EncryptionKey {
+        key: crypto.key[..16].to_vec(),
+        nonce: crypto.nonce.clone(),
+    };
+    let short_nonce = EncryptionKey {
+        key: crypto.key.clone(),
+        nonce: crypto.nonce[..8].to_vec(),
+    };
+
+    for crypto in [short_key, short_nonce] {
+        assert!(open(&crypto, &legacy).is_err());
+        assert!(open(&crypto, &sealed).is_err());
+    }
+
+    let mut unsupported = sealed.to_vec();
+    unsupported[4] = VERSION + 1;
+    assert!(open(&crypto, &Bytes::from(unsupported)).is_err());
+}
+
+#[tokio::test]
+async fn test_container_stream() {
+    use super::crypto::{encrypt_bytes, generate_key};
+    use futures_util::TryStreamExt;
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+    for legacy in [false, true] {
+        let crypto = generate_key();
+        let sealed = match legacy {
+            false => seal(&crypto, &data).unwrap(),
+            true => encrypt_bytes(&crypto, &BytesMut::from(data.as_slice())).unwrap(),
+        };
+
+        //? Feed the container in awkward pieces that straddle the header and chunk boundaries
+        let pieces: Vec<StorageResult<Bytes>> = sealed
+            .chunks(7_777)
+            .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+            .collect();
+
+        let opened: Vec<Bytes> = open_stream(crypto, stream::iter(pieces))
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(opened.concat(), data);
+    }
+}
+
+#[tokio::test]
+async fn test_container_range() {
+    use super::{crypto::generate_key, storage::MemoryStorage};
+    use futures_util::TryStreamExt;
+
+    let storage = MemoryStorage::new();
+    let crypto = generate_key();
+    let data: Vec<u8> = (0..(3 * CHUNK_SIZE as u32 + 10)).map(|i| i as u8).collect();
+
+    storage
+        .put("file.mgo", seal(&crypto, &data).unwrap())
+        .await
+        .unwrap();
+
+    let size = data.len() as u64;
+    let chunk = CHUNK_SIZE as u64;
+
+    for (start, end) in [
+        (0, 0),
+        (5, 100),
+        (chunk - 1, chunk),
+        (chunk, 2 * chunk + 3),
+        (size - 10, size - 1),
+        (0, size - 1),
+    ] {
+        let stream = open_range(&storage, "file.mgo", crypto.clone(), size, start, end)
+            .await
+            .unwrap();
+        let opened: Vec<Bytes> = stream.try_collect().await.unwrap();
+
+        assert_eq!(opened.concat(), &data[start as usize..=end as usize]);
+    }
+}
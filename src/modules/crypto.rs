@@ -1,13 +1,14 @@
 use std::fmt::Display;
 
 use aes_gcm_siv::{
-    aead::{Aead, NewAead},
+    aead::{Aead, KeyInit},
     Aes256GcmSiv, Key, Nonce,
 };
 use bytes::{Bytes, BytesMut};
 use rand::{rngs::OsRng, Rng};
 use std::io::{Error, ErrorKind};
 
+#[derive(Clone)]
 pub struct EncryptionKey {
     pub key: Vec<u8>,
     pub nonce: Vec<u8>,
@@ -29,7 +30,7 @@ pub fn generate_key() -> EncryptionKey {
     let nonce: [u8; 12] = rng.gen();
 
     let nonce = Nonce::from_slice(&nonce).to_vec();
-    let key = Key::from(key).to_vec();
+    let key = Key::<Aes256GcmSiv>::from(key).to_vec();
 
     EncryptionKey { key, nonce }
 }
@@ -38,8 +39,10 @@ pub fn encrypt_bytes(
     crypto: &EncryptionKey,
     data: &BytesMut,
 ) -> Result<Bytes, Box<dyn std::error::Error>> {
+    check_lengths(crypto)?;
+
     let nonce = Nonce::from_slice(&crypto.nonce);
-    let cipher = Aes256GcmSiv::new(Key::from_slice(&crypto.key));
+    let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&crypto.key));
 
     let data_crypt = match cipher.encrypt(nonce, data.as_ref()) {
         Ok(data) => BytesMut::from(data.as_slice()),
@@ -58,8 +61,10 @@ pub fn decrypt_bytes(
     crypto: &EncryptionKey,
     data: &Bytes,
 ) -> Result<Bytes, Box<dyn std::error::Error>> {
+    check_lengths(crypto)?;
+
     let nonce = Nonce::from_slice(&crypto.nonce);
-    let cipher = Aes256GcmSiv::new(Key::from_slice(&crypto.key));
+    let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&crypto.key));
 
     let data_decrypt = match cipher.decrypt(nonce, data.as_ref()) {
         Ok(data) => BytesMut::from(data.as_slice()),
@@ -74,6 +79,263 @@ pub fn decrypt_bytes(
     Ok(data_decrypt.freeze())
 }
 
+/// Default amount of plaintext sealed into a single chunk of the streaming format.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the authentication tag appended to every chunk.
+pub const TAG_SIZE: usize = 16;
+
+/// Derives the nonce of a single chunk by mixing its big-endian counter and the
+/// final-chunk flag into the file nonce, so chunks cannot be reordered, dropped or
+/// presented as the end of the stream without failing authentication.
+fn chunk_nonce(base: &[u8; 12], counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = *base;
+
+    for (byte, counter_byte) in nonce[3..11].iter_mut().zip(counter.to_be_bytes()) {
+        *byte ^= counter_byte;
+    }
+
+    nonce[11] ^= last as u8;
+    nonce
+}
+
+/// Refuses keys and nonces that `from_slice` would panic on, as both come straight from links.
+fn check_lengths(crypto: &EncryptionKey) -> std::io::Result<()> {
+    match crypto.key.len() == 32 && crypto.nonce.len() == 12 {
+        true => Ok(()),
+        false => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid encryption key or nonce length",
+        )),
+    }
+}
+
+fn stream_cipher(crypto: &EncryptionKey) -> std::io::Result<(Aes256GcmSiv, [u8; 12])> {
+    check_lengths(crypto)?;
+
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&crypto.nonce);
+
+    Ok((
+        Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&crypto.key)),
+        nonce,
+    ))
+}
+
+/// Encrypts a stream of bytes into fixed-size, individually authenticated chunks.
+///
+/// Data is fed through [`Encryptor::update`] as it arrives and [`Encryptor::finish`]
+/// seals the final chunk, so memory use is bounded by the chunk size.
+pub struct Encryptor {
+    cipher: Aes256GcmSiv,
+    nonce: [u8; 12],
+    counter: u64,
+    chunk_size: usize,
+    buffer: BytesMut,
+}
+
+impl Encryptor {
+    pub fn new(crypto: &EncryptionKey) -> std::io::Result<Encryptor> {
+        Encryptor::with_chunk_size(crypto, CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(
+        crypto: &EncryptionKey,
+        chunk_size: usize,
+    ) -> std::io::Result<Encryptor> {
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid chunk size"));
+        }
+
+        let (cipher, nonce) = stream_cipher(crypto)?;
+
+        Ok(Encryptor {
+            cipher,
+            nonce,
+            counter: 0,
+            chunk_size,
+            buffer: BytesMut::new(),
+        })
+    }
+
+    /// Buffers `data` and returns the ciphertext of every chunk that is known not to be the last one.
+    pub fn update(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        self.buffer.extend_from_slice(data);
+
+        let mut out = BytesMut::new();
+
+        //? A full chunk is only sealed once more data follows it, it may otherwise be the final one
+        while self.buffer.len() > self.chunk_size {
+            let chunk = self.buffer.split_to(self.chunk_size);
+            out.extend_from_slice(&self.seal(&chunk, false)?);
+        }
+
+        Ok(out.freeze())
+    }
+
+    /// Seals the remaining buffered data as the final chunk.
+    pub fn finish(mut self) -> std::io::Result<Bytes> {
+        let chunk = self.buffer.split();
+        Ok(Bytes::from(self.seal(&chunk, true)?))
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> std::io::Result<Vec<u8>> {
+        let nonce = chunk_nonce(&self.nonce, self.counter, last);
+        self.counter += 1;
+
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| Error::other("Failed to encrypt data"))
+    }
+}
+
+/// Decrypts and authenticates a stream produced by [`Encryptor`].
+///
+/// Truncated, reordered or tampered chunks make [`Decryptor::update`] or
+/// [`Decryptor::finish`] fail instead of returning partial plaintext.
+pub struct Decryptor {
+    cipher: Aes256GcmSiv,
+    nonce: [u8; 12],
+    counter: u64,
+    chunk_size: usize,
+    buffer: BytesMut,
+}
+
+impl Decryptor {
+    pub fn new(crypto: &EncryptionKey) -> std::io::Result<Decryptor> {
+        Decryptor::with_chunk_size(crypto, CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(
+        crypto: &EncryptionKey,
+        chunk_size: usize,
+    ) -> std::io::Result<Decryptor> {
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid chunk size"));
+        }
+
+        let (cipher, nonce) = stream_cipher(crypto)?;
+
+        Ok(Decryptor {
+            cipher,
+            nonce,
+            counter: 0,
+            chunk_size,
+            buffer: BytesMut::new(),
+        })
+    }
+
+    /// Buffers `data` and returns the plaintext of every chunk that is known not to be the last one.
+    pub fn update(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        self.buffer.extend_from_slice(data);
+
+        let sealed_size = self.chunk_size + TAG_SIZE;
+        let mut out = BytesMut::new();
+
+        while self.buffer.len() > sealed_size {
+            let chunk = self.buffer.split_to(sealed_size);
+            out.extend_from_slice(&self.open(&chunk, false)?);
+        }
+
+        Ok(out.freeze())
+    }
+
+    /// Opens the final chunk, failing if the stream was truncated.
+    pub fn finish(mut self) -> std::io::Result<Bytes> {
+        let chunk = self.buffer.split();
+        Ok(Bytes::from(self.open(&chunk, true)?))
+    }
+
+    /// Continues decrypting at chunk `index`, for reading a range out of the middle of a stream.
+    pub fn seek(&mut self, index: u64) {
+        self.counter = index;
+        self.buffer.clear();
+    }
+
+    /// Opens the remaining buffered chunk as a regular one, for ranges that end before the final chunk.
+    ///
+    /// Fails unless every chunk before index `until` has been opened, so a source that stops
+    /// early can not pass for a shorter range.
+    pub fn finish_range(mut self, until: u64) -> std::io::Result<Bytes> {
+        let chunk = self.buffer.split();
+
+        let out = match chunk.is_empty() {
+            true => Vec::new(),
+            false => self.open(&chunk, false)?,
+        };
+
+        if self.counter != until {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "The range ended before its last chunk",
+            ));
+        }
+
+        Ok(Bytes::from(out))
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> std::io::Result<Vec<u8>> {
+        let nonce = chunk_nonce(&self.nonce, self.counter, last);
+        self.counter += 1;
+
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to decrypt data"))
+    }
+}
+
+/// Short texts are padded to a multiple of this many bytes before being sealed, so their
+/// ciphertext does not reveal their exact length.
+const TEXT_PADDING: usize = 64;
+
+/// Encrypts a short piece of metadata such as a filename with the file key.
+///
+/// Every text gets a random nonce, which is stored in front of the ciphertext.
+pub fn seal_text(key: &[u8], text: &str) -> std::io::Result<String> {
+    if key.len() != 32 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid encryption key length",
+        ));
+    }
+
+    let nonce: [u8; 12] = OsRng.gen();
+
+    //? Filenames and MIME types never contain NUL bytes, so they can be stripped again safely
+    let mut padded = text.as_bytes().to_vec();
+    padded.resize((padded.len() / TEXT_PADDING + 1) * TEXT_PADDING, 0);
+
+    let sealed = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(key))
+        .encrypt(Nonce::from_slice(&nonce), padded.as_slice())
+        .map_err(|_| Error::other("Failed to encrypt data"))?;
+
+    Ok(base64::encode([&nonce[..], &sealed].concat()))
+}
+
+/// Decrypts a text sealed by [`seal_text`], `None` if the key is wrong or the text was altered.
+pub fn open_text(key: &[u8], sealed: &str) -> Option<String> {
+    if key.len() != 32 {
+        return None;
+    }
+
+    let sealed = base64::decode(sealed).ok()?;
+
+    if sealed.len() < 12 {
+        return None;
+    }
+
+    let (nonce, sealed) = sealed.split_at(12);
+    let mut text = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .ok()?;
+
+    while text.last() == Some(&0) {
+        text.pop();
+    }
+
+    String::from_utf8(text).ok()
+}
+
 #[test]
 fn test_crypto() {
     let crypto = generate_key();
@@ -105,3 +367,83 @@ fn test_crypto() {
 
     assert_eq!(data.as_ref(), decrypted.as_ref());
 }
+
+#[test]
+fn test_stream_crypto() {
+    let crypto = generate_key();
+    let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+
+    for chunk_size in [1, 7, 1024, 10_000, CHUNK_SIZE] {
+        let mut encryptor = Encryptor::with_chunk_size(&crypto, chunk_size).unwrap();
+        let mut encrypted = BytesMut::new();
+
+        for part in data.chunks(333) {
+            encrypted.extend_from_slice(&encryptor.update(part).unwrap());
+        }
+        encrypted.extend_from_slice(&encryptor.finish().unwrap());
+
+        let mut decryptor = Decryptor::with_chunk_size(&crypto, chunk_size).unwrap();
+        let mut decrypted = BytesMut::new();
+
+        for part in encrypted.chunks(1000) {
+            decrypted.extend_from_slice(&decryptor.update(part).unwrap());
+        }
+        decrypted.extend_from_slice(&decryptor.finish().unwrap());
+
+        assert_eq!(data.as_slice(), decrypted.as_ref());
+    }
+}
+
+#[test]
+fn test_stream_crypto_tampering() {
+    let crypto = generate_key();
+    let sealed = 4 + TAG_SIZE;
+
+    let mut encryptor = Encryptor::with_chunk_size(&crypto, 4).unwrap();
+    let mut encrypted = BytesMut::from(encryptor.update(b"Hello World!").unwrap().as_ref());
+    encrypted.extend_from_slice(&encryptor.finish().unwrap());
+    assert_eq!(encrypted.len(), 3 * sealed);
+
+    let decrypt = |data: &[u8]| -> std::io::Result<Bytes> {
+        let mut decryptor = Decryptor::with_chunk_size(&crypto, 4)?;
+        let mut out = BytesMut::from(decryptor.update(data)?.as_ref());
+        out.extend_from_slice(&decryptor.finish()?);
+        Ok(out.freeze())
+    };
+
+    assert_eq!(decrypt(&encrypted).unwrap(), "Hello World!");
+
+    //? Truncated at a chunk boundary
+    assert!(decrypt(&encrypted[..2 * sealed]).is_err());
+
+    //? Reordered chunks
+    let mut reordered = encrypted[sealed..2 * sealed].to_vec();
+    reordered.extend_from_slice(&encrypted[..sealed]);
+    reordered.extend_from_slice(&encrypted[2 * sealed..]);
+    assert!(decrypt(&reordered).is_err());
+
+    //? A range of the first two chunks whose source stops after one
+    let range = |data: &[u8]| -> std::io::Result<Bytes> {
+        let mut decryptor = Decryptor::with_chunk_size(&crypto, 4)?;
+        let mut out = BytesMut::from(decryptor.update(data)?.as_ref());
+        out.extend_from_slice(&decryptor.finish_range(2)?);
+        Ok(out.freeze())
+    };
+
+    assert_eq!(range(&encrypted[..2 * sealed]).unwrap(), "Hello Wo");
+    assert!(range(&encrypted[..sealed]).is_err());
+    assert!(range(&[]).is_err());
+}
+
+#[test]
+fn test_sealed_text() {
+    let crypto = generate_key();
+    let sealed = seal_text(&crypto.key, "salary_review_2024.pdf").unwrap();
+
+    assert_eq!(base64::decode(&sealed).unwrap().len(), 12 + 64 + TAG_SIZE);
+    assert_eq!(
+        open_text(&crypto.key, &sealed).as_deref(),
+        Some("salary_review_2024.pdf")
+    );
+    assert_eq!(open_text(&generate_key().key, &sealed), None);
+}
@@ -0,0 +1,218 @@
+//! Pluggable database backends for users, files and everything attached to them.
+//!
+//! Route handlers only ever talk to an [`Arc<dyn DatabaseBackend>`], which is made of one
+//! repository trait per kind of record. MongoDB is the default, PostgreSQL and SQLite share an
+//! SQL implementation so small deployments can run on a single file.
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+use super::config::{DatabaseConfig, DatabaseKind};
+use crate::structs::{
+    files::File,
+    invites::Invite,
+    roles::Role,
+    tokens::ApiToken,
+    users::{User, UserListRequest, UserUpdateRequest},
+    vault::Vault,
+    Permissions,
+};
+
+pub mod mongo;
+pub mod sql;
+
+pub use self::mongo::MongoDatabase;
+pub use self::sql::SqlDatabase;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Taken(&'static str), //? A unique field such as the username is already in use
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type DatabaseResult<T> = Result<T, DatabaseError>;
+
+/// Key of the marker claimed by the creation of the first account.
+const BOOTSTRAP: &str = "bootstrap";
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Taken(field) => write!(f, "the {} is already taken", field),
+            DatabaseError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<bson::ser::Error> for DatabaseError {
+    fn from(e: bson::ser::Error) -> Self {
+        DatabaseError::Backend(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(e: serde_json::Error) -> Self {
+        DatabaseError::Backend(Box::new(e))
+    }
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn count_users(&self) -> DatabaseResult<u64>;
+
+    /// Claims the creation of the first account, `true` for exactly one caller per install.
+    async fn claim_bootstrap(&self) -> DatabaseResult<bool>;
+
+    /// Gives the claim back when the first account could not be created.
+    async fn release_bootstrap(&self) -> DatabaseResult<()>;
+
+    /// Fails with [`DatabaseError::Taken`] if the username or email is in use, ignoring case.
+    async fn insert_user(&self, user: &User) -> DatabaseResult<()>;
+    async fn find_user(&self, id: ObjectId) -> DatabaseResult<Option<User>>;
+
+    /// Finds the user whose primary token has the given hash.
+    async fn find_user_by_token(&self, hash: &str) -> DatabaseResult<Option<User>>;
+
+    /// Finds the user whose username or email is exactly `login`.
+    async fn find_user_by_login(&self, login: &str) -> DatabaseResult<Option<User>>;
+
+    /// Lists the users matching `filter` oldest first, with the total number of matches.
+    async fn list_users(
+        &self,
+        filter: &UserListRequest,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<User>, u64)>;
+    async fn count_users_with_role(&self, role: &str) -> DatabaseResult<u64>;
+
+    /// Applies the fields that are set, fails like [`UserRepository::insert_user`].
+    async fn update_user(&self, id: ObjectId, update: &UserUpdateRequest) -> DatabaseResult<()>;
+    async fn set_suspended(&self, id: ObjectId, suspended: bool) -> DatabaseResult<()>;
+    async fn set_user_token(&self, id: ObjectId, hash: &str) -> DatabaseResult<()>;
+
+    /// Replaces the password hash, but only if it is still `current`.
+    async fn replace_password(&self, id: ObjectId, current: &str, hash: &str)
+        -> DatabaseResult<()>;
+
+    /// Adds `delta` bytes, which may be negative, to the quota the user has used.
+    async fn add_used_quota(&self, id: ObjectId, delta: i64) -> DatabaseResult<()>;
+
+    /// Sets up the user's vault, `false` if they already have one.
+    async fn create_vault(&self, id: ObjectId, vault: &Vault) -> DatabaseResult<bool>;
+
+    /// Removes the user's vault together with every link sealed to it.
+    async fn delete_vault(&self, id: ObjectId) -> DatabaseResult<()>;
+
+    /// Deletes the user together with their files and tokens.
+    async fn delete_user(&self, id: ObjectId) -> DatabaseResult<()>;
+}
+
+#[async_trait]
+pub trait FileRepository: Send + Sync {
+    async fn insert_file(&self, file: &File) -> DatabaseResult<()>;
+
+    /// Finds a file by its public ID, or by its hash for files uploaded before public IDs existed.
+    async fn find_file(&self, id: &str) -> DatabaseResult<Option<File>>;
+    async fn public_id_exists(&self, id: &str) -> DatabaseResult<bool>;
+
+    async fn delete_file(&self, id: ObjectId) -> DatabaseResult<Option<File>>;
+
+    /// Counts a download of an unpurged file that has some left, returning the updated file.
+    async fn claim_download(&self, id: ObjectId) -> DatabaseResult<Option<File>>;
+
+    /// Marks the file as purged, `false` if it already was.
+    async fn mark_purged(&self, id: ObjectId) -> DatabaseResult<bool>;
+
+    /// The files that expired by `now` and have not been purged yet.
+    async fn expired_files(&self, now: DateTime<Utc>) -> DatabaseResult<Vec<File>>;
+
+    /// Deletes the purged files that expired by `before`.
+    async fn forget_purged(&self, before: DateTime<Utc>) -> DatabaseResult<u64>;
+
+    /// The number and total size of the files that have not been purged.
+    async fn file_totals(&self) -> DatabaseResult<(i64, i64)>;
+
+    /// Lists the uploader's unpurged files with a sealed link newest first, with their total.
+    async fn list_vault_files(
+        &self,
+        uploader: ObjectId,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<File>, u64)>;
+}
+
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    async fn insert_token(&self, token: &ApiToken) -> DatabaseResult<()>;
+    async fn find_token(&self, hash: &str) -> DatabaseResult<Option<ApiToken>>;
+    async fn list_tokens(&self, user: ObjectId) -> DatabaseResult<Vec<ApiToken>>;
+
+    /// Records that the token was just used.
+    async fn touch_token(&self, id: ObjectId) -> DatabaseResult<()>;
+    async fn set_token_hash(&self, id: ObjectId, hash: &str) -> DatabaseResult<()>;
+
+    /// Deletes a token of `user`, `false` if they have no such token.
+    async fn delete_token(&self, id: ObjectId, user: ObjectId) -> DatabaseResult<bool>;
+
+    /// Deletes the tokens that expired by `now`.
+    async fn forget_expired_tokens(&self, now: DateTime<Utc>) -> DatabaseResult<u64>;
+}
+
+#[async_trait]
+pub trait RoleRepository: Send + Sync {
+    async fn find_role(&self, name: &str) -> DatabaseResult<Option<Role>>;
+    async fn list_roles(&self) -> DatabaseResult<Vec<Role>>;
+
+    /// Creates the role or replaces its permissions and maximum file lifetime.
+    async fn put_role(
+        &self,
+        name: &str,
+        permissions: Permissions,
+        max_file_lifetime: Option<i64>,
+    ) -> DatabaseResult<()>;
+
+    /// Creates the role unless one with the same name exists.
+    async fn ensure_role(&self, name: &str, permissions: Permissions) -> DatabaseResult<()>;
+    async fn delete_role(&self, name: &str) -> DatabaseResult<bool>;
+}
+
+#[async_trait]
+pub trait InviteRepository: Send + Sync {
+    async fn insert_invite(&self, invite: &Invite) -> DatabaseResult<()>;
+    async fn list_invites(&self) -> DatabaseResult<Vec<Invite>>;
+    async fn delete_invite(&self, id: ObjectId) -> DatabaseResult<bool>;
+
+    /// Uses up one redemption of the invite with the given hash, `None` if it is unknown,
+    /// expired or has no uses left. The check and the increment happen atomically.
+    async fn redeem_invite(&self, hash: &str) -> DatabaseResult<Option<Invite>>;
+
+    /// Gives back a redemption when the registration it was used for failed.
+    async fn restore_invite(&self, id: ObjectId) -> DatabaseResult<()>;
+}
+
+#[async_trait]
+pub trait DatabaseBackend:
+    UserRepository + FileRepository + TokenRepository + RoleRepository + InviteRepository + fmt::Debug
+{
+    /// A short, human readable name used in the startup logs.
+    fn name(&self) -> &'static str;
+
+    /// Creates missing tables and indexes and upgrades records written by older versions,
+    /// fails if existing data violates a unique index.
+    async fn setup(&self) -> DatabaseResult<()>;
+}
+
+/// Connects to the database selected in `config.toml`.
+pub async fn from_config(config: &DatabaseConfig) -> DatabaseResult<Arc<dyn DatabaseBackend>> {
+    match config.kind {
+        DatabaseKind::Mongodb => Ok(Arc::new(MongoDatabase::connect(config).await?)),
+        DatabaseKind::Postgres | DatabaseKind::Sqlite => {
+            Ok(Arc::new(SqlDatabase::connect(config).await?))
+        }
+    }
+}
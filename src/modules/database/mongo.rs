@@ -0,0 +1,686 @@
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, to_bson, Document};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    options::{ClientOptions, FindOneAndUpdateOptions, FindOptions, ReturnDocument, UpdateOptions},
+    Client, Collection, Database,
+};
+
+use super::{
+    DatabaseBackend, DatabaseError, DatabaseResult, FileRepository, InviteRepository,
+    RoleRepository, TokenRepository, UserRepository, BOOTSTRAP,
+};
+use crate::{
+    modules::{
+        config::DatabaseConfig,
+        indexes::{self, duplicate_index, EMAIL_INDEX, USERNAME_INDEX},
+        roles::{ADMIN_ROLE, DEFAULT_ROLE},
+    },
+    structs::{
+        files::File,
+        invites::Invite,
+        roles::Role,
+        tokens::ApiToken,
+        users::{User, UserListRequest, UserUpdateRequest},
+        vault::Vault,
+        Permissions,
+    },
+};
+
+/// Bit of the old `ADMIN` privilege flag.
+const LEGACY_ADMIN: i64 = 1;
+
+impl From<mongodb::error::Error> for DatabaseError {
+    fn from(e: mongodb::error::Error) -> Self {
+        match duplicate_index(&e) {
+            Some(USERNAME_INDEX) => DatabaseError::Taken("username"),
+            Some(EMAIL_INDEX) => DatabaseError::Taken("email"),
+            _ => DatabaseError::Backend(Box::new(e)),
+        }
+    }
+}
+
+/// Keeps every kind of record in a collection of its own.
+#[derive(Debug, Clone)]
+pub struct MongoDatabase {
+    database: Database,
+}
+
+impl MongoDatabase {
+    pub async fn connect(config: &DatabaseConfig) -> DatabaseResult<MongoDatabase> {
+        let options = ClientOptions::parse(&config.uri).await?;
+        let client = Client::with_options(options)?;
+
+        Ok(MongoDatabase::new(client.database(&config.db_name)))
+    }
+
+    pub fn new(database: Database) -> MongoDatabase {
+        MongoDatabase { database }
+    }
+
+    fn users(&self) -> Collection<User> {
+        self.database.collection("users")
+    }
+
+    fn files(&self) -> Collection<File> {
+        self.database.collection("files")
+    }
+
+    fn tokens(&self) -> Collection<ApiToken> {
+        self.database.collection("tokens")
+    }
+
+    fn roles(&self) -> Collection<Role> {
+        self.database.collection("roles")
+    }
+
+    fn invites(&self) -> Collection<Invite> {
+        self.database.collection("invites")
+    }
+
+    fn meta(&self) -> Collection<Document> {
+        self.database.collection("meta")
+    }
+}
+
+fn after() -> FindOneAndUpdateOptions {
+    FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build()
+}
+
+fn upsert() -> UpdateOptions {
+    UpdateOptions::builder().upsert(true).build()
+}
+
+/// Escapes `text` so it can be used as a literal inside a MongoDB `$regex`.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn user_filter(filter: &UserListRequest) -> Document {
+    let mut document = Document::new();
+
+    if let Some(ref username) = filter.username {
+        document.insert(
+            "username",
+            doc! {"$regex": escape_regex(username), "$options": "i"},
+        );
+    }
+
+    if let Some(ref email) = filter.email {
+        document.insert(
+            "email",
+            doc! {"$regex": escape_regex(email), "$options": "i"},
+        );
+    }
+
+    if let Some(suspended) = filter.suspended {
+        //? Users created before suspensions existed have no `suspended` field
+        document.insert(
+            "suspended",
+            if suspended {
+                doc! {"$eq": true}
+            } else {
+                doc! {"$ne": true}
+            },
+        );
+    }
+
+    if let Some(ref role) = filter.role {
+        document.insert("role", role);
+    }
+
+    document
+}
+
+#[async_trait]
+impl UserRepository for MongoDatabase {
+    async fn count_users(&self) -> DatabaseResult<u64> {
+        Ok(self.users().count_documents(None, None).await?)
+    }
+
+    async fn claim_bootstrap(&self) -> DatabaseResult<bool> {
+        let result = self
+            .meta()
+            .update_one(
+                doc! {"_id": BOOTSTRAP},
+                doc! {"$setOnInsert": {"created_at": Utc::now()}},
+                upsert(),
+            )
+            .await?;
+
+        Ok(result.upserted_id.is_some())
+    }
+
+    async fn release_bootstrap(&self) -> DatabaseResult<()> {
+        self.meta()
+            .delete_one(doc! {"_id": BOOTSTRAP}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_user(&self, user: &User) -> DatabaseResult<()> {
+        self.users().insert_one(user, None).await?;
+        Ok(())
+    }
+
+    async fn find_user(&self, id: ObjectId) -> DatabaseResult<Option<User>> {
+        Ok(self.users().find_one(doc! {"_id": id}, None).await?)
+    }
+
+    async fn find_user_by_token(&self, hash: &str) -> DatabaseResult<Option<User>> {
+        Ok(self.users().find_one(doc! {"token": hash}, None).await?)
+    }
+
+    async fn find_user_by_login(&self, login: &str) -> DatabaseResult<Option<User>> {
+        Ok(self
+            .users()
+            .find_one(doc! {"$or": [{"username": login}, {"email": login}]}, None)
+            .await?)
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserListRequest,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<User>, u64)> {
+        let filter = user_filter(filter);
+        let total = self.users().count_documents(filter.clone(), None).await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! {"created_at": 1})
+            .skip(skip)
+            .limit(limit as i64)
+            .build();
+
+        let found = self
+            .users()
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok((found, total))
+    }
+
+    async fn count_users_with_role(&self, role: &str) -> DatabaseResult<u64> {
+        Ok(self
+            .users()
+            .count_documents(doc! {"role": role}, None)
+            .await?)
+    }
+
+    async fn update_user(&self, id: ObjectId, update: &UserUpdateRequest) -> DatabaseResult<()> {
+        let mut set = doc! {"updated_at": Utc::now()};
+
+        if let Some(ref email) = update.email {
+            set.insert("email", email);
+        }
+
+        if let Some(ref role) = update.role {
+            set.insert("role", role);
+        }
+
+        if let Some(quota) = update.quota {
+            set.insert("quota.available", quota);
+        }
+
+        if let Some(slug) = update.slug {
+            set.insert("slug", to_bson(&slug)?);
+        }
+
+        self.users()
+            .update_one(doc! {"_id": id}, doc! {"$set": set}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_suspended(&self, id: ObjectId, suspended: bool) -> DatabaseResult<()> {
+        self.users()
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {"suspended": suspended, "updated_at": Utc::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_token(&self, id: ObjectId, hash: &str) -> DatabaseResult<()> {
+        self.users()
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {"token": hash, "updated_at": Utc::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn replace_password(
+        &self,
+        id: ObjectId,
+        current: &str,
+        hash: &str,
+    ) -> DatabaseResult<()> {
+        self.users()
+            .update_one(
+                doc! {"_id": id, "password": current},
+                doc! {"$set": {"password": hash, "updated_at": Utc::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_used_quota(&self, id: ObjectId, delta: i64) -> DatabaseResult<()> {
+        self.users()
+            .update_one(
+                doc! {"_id": id},
+                doc! {
+                    "$inc": {"quota.used": delta},
+                    "$set": {"updated_at": Utc::now()},
+                },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_vault(&self, id: ObjectId, vault: &Vault) -> DatabaseResult<bool> {
+        let result = self
+            .users()
+            .update_one(
+                doc! {"_id": id, "vault": null},
+                doc! {"$set": {"vault": to_bson(vault)?, "updated_at": Utc::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(result.modified_count > 0)
+    }
+
+    async fn delete_vault(&self, id: ObjectId) -> DatabaseResult<()> {
+        self.users()
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$unset": {"vault": ""}, "$set": {"updated_at": Utc::now()}},
+                None,
+            )
+            .await?;
+
+        self.files()
+            .update_many(
+                doc! {"uploader": id},
+                doc! {"$unset": {"vault_key": ""}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_user(&self, id: ObjectId) -> DatabaseResult<()> {
+        self.users().delete_one(doc! {"_id": id}, None).await?;
+        self.files()
+            .delete_many(doc! {"uploader": id}, None)
+            .await?;
+        self.tokens().delete_many(doc! {"user": id}, None).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileRepository for MongoDatabase {
+    async fn insert_file(&self, file: &File) -> DatabaseResult<()> {
+        self.files().insert_one(file, None).await?;
+        Ok(())
+    }
+
+    async fn find_file(&self, id: &str) -> DatabaseResult<Option<File>> {
+        Ok(self.files().find_one(File::lookup(id), None).await?)
+    }
+
+    async fn public_id_exists(&self, id: &str) -> DatabaseResult<bool> {
+        Ok(self.files().count_documents(doc! {"id": id}, None).await? > 0)
+    }
+
+    async fn delete_file(&self, id: ObjectId) -> DatabaseResult<Option<File>> {
+        Ok(self
+            .files()
+            .find_one_and_delete(doc! {"_id": id}, None)
+            .await?)
+    }
+
+    async fn claim_download(&self, id: ObjectId) -> DatabaseResult<Option<File>> {
+        Ok(self
+            .files()
+            .find_one_and_update(
+                doc! {
+                    "_id": id,
+                    "purged": {"$ne": true},
+                    "$expr": {"$lt": ["$downloads", "$max_downloads"]},
+                },
+                doc! {"$inc": {"downloads": 1}},
+                after(),
+            )
+            .await?)
+    }
+
+    async fn mark_purged(&self, id: ObjectId) -> DatabaseResult<bool> {
+        let result = self
+            .files()
+            .update_one(
+                doc! {"_id": id, "purged": {"$ne": true}},
+                doc! {"$set": {"purged": true}},
+                None,
+            )
+            .await?;
+
+        Ok(result.modified_count > 0)
+    }
+
+    async fn expired_files(&self, now: DateTime<Utc>) -> DatabaseResult<Vec<File>> {
+        Ok(self
+            .files()
+            .find(
+                doc! {
+                    "expires_at": {"$lte": bson::DateTime::from_chrono(now)},
+                    "purged": {"$ne": true},
+                },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn forget_purged(&self, before: DateTime<Utc>) -> DatabaseResult<u64> {
+        let result = self
+            .files()
+            .delete_many(
+                doc! {
+                    "purged": true,
+                    "expires_at": {"$lte": bson::DateTime::from_chrono(before)},
+                },
+                None,
+            )
+            .await?;
+
+        Ok(result.deleted_count)
+    }
+
+    async fn file_totals(&self) -> DatabaseResult<(i64, i64)> {
+        let totals: Vec<Document> = self
+            .files()
+            .aggregate(
+                [
+                    doc! {"$match": {"purged": {"$ne": true}}},
+                    doc! {"$group": {"_id": null, "count": {"$sum": 1}, "size": {"$sum": "$size"}}},
+                ],
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        //? `$sum` yields an int32 or int64 depending on the magnitude of the total
+        let total = |field: &str| {
+            totals
+                .first()
+                .and_then(|totals| totals.get(field))
+                .and_then(|value| value.as_i64().or_else(|| value.as_i32().map(i64::from)))
+                .unwrap_or(0)
+        };
+
+        Ok((total("count"), total("size")))
+    }
+
+    async fn list_vault_files(
+        &self,
+        uploader: ObjectId,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<File>, u64)> {
+        let filter = doc! {
+            "uploader": uploader,
+            "vault_key": {"$ne": null},
+            "purged": {"$ne": true},
+        };
+
+        let total = self.files().count_documents(filter.clone(), None).await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! {"created_at": -1})
+            .skip(skip)
+            .limit(limit as i64)
+            .build();
+
+        let found = self
+            .files()
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok((found, total))
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MongoDatabase {
+    async fn insert_token(&self, token: &ApiToken) -> DatabaseResult<()> {
+        self.tokens().insert_one(token, None).await?;
+        Ok(())
+    }
+
+    async fn find_token(&self, hash: &str) -> DatabaseResult<Option<ApiToken>> {
+        Ok(self.tokens().find_one(doc! {"hash": hash}, None).await?)
+    }
+
+    async fn list_tokens(&self, user: ObjectId) -> DatabaseResult<Vec<ApiToken>> {
+        Ok(self
+            .tokens()
+            .find(doc! {"user": user}, None)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn touch_token(&self, id: ObjectId) -> DatabaseResult<()> {
+        self.tokens()
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {"last_used_at": bson::DateTime::now()}},
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_token_hash(&self, id: ObjectId, hash: &str) -> DatabaseResult<()> {
+        self.tokens()
+            .update_one(doc! {"_id": id}, doc! {"$set": {"hash": hash}}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_token(&self, id: ObjectId, user: ObjectId) -> DatabaseResult<bool> {
+        let result = self
+            .tokens()
+            .delete_one(doc! {"_id": id, "user": user}, None)
+            .await?;
+
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn forget_expired_tokens(&self, now: DateTime<Utc>) -> DatabaseResult<u64> {
+        let result = self
+            .tokens()
+            .delete_many(
+                doc! {"expires_at": {"$lte": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl RoleRepository for MongoDatabase {
+    async fn find_role(&self, name: &str) -> DatabaseResult<Option<Role>> {
+        Ok(self.roles().find_one(doc! {"_id": name}, None).await?)
+    }
+
+    async fn list_roles(&self) -> DatabaseResult<Vec<Role>> {
+        Ok(self.roles().find(None, None).await?.try_collect().await?)
+    }
+
+    async fn put_role(
+        &self,
+        name: &str,
+        permissions: Permissions,
+        max_file_lifetime: Option<i64>,
+    ) -> DatabaseResult<()> {
+        self.roles()
+            .update_one(
+                doc! {"_id": name},
+                doc! {
+                    "$set": {
+                        "permissions": to_bson(&permissions)?,
+                        "max_file_lifetime": max_file_lifetime,
+                        "updated_at": Utc::now(),
+                    },
+                    "$setOnInsert": {"created_at": Utc::now()},
+                },
+                upsert(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ensure_role(&self, name: &str, permissions: Permissions) -> DatabaseResult<()> {
+        self.roles()
+            .update_one(
+                doc! {"_id": name},
+                doc! {"$setOnInsert": {
+                    "permissions": to_bson(&permissions)?,
+                    "created_at": Utc::now(),
+                    "updated_at": Utc::now(),
+                }},
+                upsert(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_role(&self, name: &str) -> DatabaseResult<bool> {
+        let result = self.roles().delete_one(doc! {"_id": name}, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for MongoDatabase {
+    async fn insert_invite(&self, invite: &Invite) -> DatabaseResult<()> {
+        self.invites().insert_one(invite, None).await?;
+        Ok(())
+    }
+
+    async fn list_invites(&self) -> DatabaseResult<Vec<Invite>> {
+        Ok(self.invites().find(None, None).await?.try_collect().await?)
+    }
+
+    async fn delete_invite(&self, id: ObjectId) -> DatabaseResult<bool> {
+        let result = self.invites().delete_one(doc! {"_id": id}, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn redeem_invite(&self, hash: &str) -> DatabaseResult<Option<Invite>> {
+        Ok(self
+            .invites()
+            .find_one_and_update(
+                doc! {
+                    "hash": hash,
+                    "$and": [
+                        {"$or": [{"expires_at": null}, {"expires_at": {"$gt": bson::DateTime::now()}}]},
+                        {"$or": [{"max_uses": null}, {"$expr": {"$lt": ["$uses", "$max_uses"]}}]},
+                    ],
+                },
+                doc! {"$inc": {"uses": 1}},
+                after(),
+            )
+            .await?)
+    }
+
+    async fn restore_invite(&self, id: ObjectId) -> DatabaseResult<()> {
+        self.invites()
+            .update_one(doc! {"_id": id}, doc! {"$inc": {"uses": -1}}, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatabaseBackend for MongoDatabase {
+    fn name(&self) -> &'static str {
+        "MongoDB"
+    }
+
+    async fn setup(&self) -> DatabaseResult<()> {
+        indexes::setup(&self.database).await?;
+
+        //? Users created before roles existed only have the `privileges` flags
+        self.users()
+            .update_many(
+                doc! {"role": {"$exists": false}, "privileges.bits": {"$bitsAllSet": LEGACY_ADMIN}},
+                doc! {"$set": {"role": ADMIN_ROLE}, "$unset": {"privileges": ""}},
+                None,
+            )
+            .await?;
+
+        self.users()
+            .update_many(
+                doc! {"role": {"$exists": false}},
+                doc! {"$set": {"role": DEFAULT_ROLE}, "$unset": {"privileges": ""}},
+                None,
+            )
+            .await?;
+
+        //? Installs that already have accounts are past their bootstrap, empty ones start over even
+        //? if the first registration never finished
+        match self.count_users().await? {
+            0 => self.release_bootstrap().await?,
+            _ => {
+                self.claim_bootstrap().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1181 @@
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions, AnyRow},
+    AnyPool, Row,
+};
+
+use super::{
+    DatabaseBackend, DatabaseError, DatabaseResult, FileRepository, InviteRepository,
+    RoleRepository, TokenRepository, UserRepository, BOOTSTRAP,
+};
+use crate::{
+    modules::{
+        config::{DatabaseConfig, DatabaseKind},
+        container::Compression,
+        indexes::{EMAIL_INDEX, USERNAME_INDEX},
+    },
+    structs::{
+        files::File,
+        invites::Invite,
+        roles::Role,
+        tokens::ApiToken,
+        users::{User, UserListRequest, UserQuota, UserUpdateRequest},
+        vault::Vault,
+        Permissions, Scopes,
+    },
+};
+
+//? Timestamps are stored as milliseconds and flags as 0 or 1, which both databases read alike
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS users (
+        _id TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        email TEXT NOT NULL,
+        password TEXT NOT NULL,
+        quota_used BIGINT NOT NULL,
+        quota_available BIGINT NOT NULL,
+        role TEXT NOT NULL,
+        token TEXT NOT NULL UNIQUE,
+        suspended BIGINT NOT NULL DEFAULT 0,
+        vault TEXT,
+        slug TEXT,
+        created_at BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL
+    )",
+    "CREATE UNIQUE INDEX IF NOT EXISTS username_unique ON users (lower(username))",
+    "CREATE UNIQUE INDEX IF NOT EXISTS email_unique ON users (lower(email))",
+    "CREATE INDEX IF NOT EXISTS users_role ON users (role)",
+    "CREATE TABLE IF NOT EXISTS tokens (
+        _id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        hash TEXT NOT NULL UNIQUE,
+        scopes BIGINT NOT NULL,
+        created_at BIGINT NOT NULL,
+        last_used_at BIGINT,
+        expires_at BIGINT
+    )",
+    "CREATE INDEX IF NOT EXISTS tokens_user ON tokens (user_id)",
+    "CREATE TABLE IF NOT EXISTS roles (
+        _id TEXT PRIMARY KEY,
+        permissions BIGINT NOT NULL,
+        max_file_lifetime BIGINT,
+        created_at BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS invites (
+        _id TEXT PRIMARY KEY,
+        hash TEXT NOT NULL UNIQUE,
+        created_by TEXT NOT NULL,
+        uses BIGINT NOT NULL,
+        max_uses BIGINT,
+        role TEXT,
+        quota BIGINT,
+        created_at BIGINT NOT NULL,
+        expires_at BIGINT
+    )",
+    "CREATE TABLE IF NOT EXISTS files (
+        _id TEXT PRIMARY KEY,
+        id TEXT UNIQUE,
+        filename TEXT NOT NULL,
+        mimetype TEXT NOT NULL,
+        uploader TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        dkey TEXT NOT NULL,
+        size BIGINT NOT NULL,
+        blob TEXT,
+        created_at BIGINT NOT NULL,
+        expires_at BIGINT,
+        purged BIGINT NOT NULL DEFAULT 0,
+        max_downloads BIGINT,
+        downloads BIGINT NOT NULL DEFAULT 0,
+        lock TEXT,
+        sealed BIGINT NOT NULL DEFAULT 0,
+        vault_key TEXT,
+        compression BIGINT NOT NULL DEFAULT 0
+    )",
+    "CREATE INDEX IF NOT EXISTS files_expires_at ON files (expires_at)",
+    "CREATE TABLE IF NOT EXISTS meta (
+        _id TEXT PRIMARY KEY,
+        created_at BIGINT NOT NULL
+    )",
+];
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(e: sqlx::Error) -> Self {
+        if let sqlx::Error::Database(ref error) = e {
+            if error.is_unique_violation() {
+                //? SQLite only names the index in the message
+                for (index, field) in [(USERNAME_INDEX, "username"), (EMAIL_INDEX, "email")] {
+                    if error.constraint() == Some(index) || error.message().contains(index) {
+                        return DatabaseError::Taken(field);
+                    }
+                }
+            }
+        }
+
+        DatabaseError::Backend(Box::new(e))
+    }
+}
+
+fn invalid<T: Into<Box<dyn std::error::Error + Send + Sync>>>(e: T) -> DatabaseError {
+    DatabaseError::Backend(e.into())
+}
+
+/// Keeps records in PostgreSQL or SQLite, which understand the same SQL for everything here.
+#[derive(Debug, Clone)]
+pub struct SqlDatabase {
+    pool: AnyPool,
+    name: &'static str,
+}
+
+impl SqlDatabase {
+    pub async fn connect(config: &DatabaseConfig) -> DatabaseResult<SqlDatabase> {
+        let (name, schemes): (_, &[&str]) = match config.kind {
+            DatabaseKind::Postgres => ("PostgreSQL", &["postgres:", "postgresql:"]),
+            DatabaseKind::Sqlite => ("SQLite", &["sqlite:"]),
+            DatabaseKind::Mongodb => return Err(invalid("MongoDB is not an SQL database")),
+        };
+
+        if !schemes.iter().any(|scheme| config.uri.starts_with(scheme)) {
+            return Err(invalid(format!(
+                "The URI of a {} database must start with {}",
+                name, schemes[0]
+            )));
+        }
+
+        install_default_drivers();
+
+        //? Every connection to `sqlite::memory:` opens a database of its own
+        let connections = match config.uri.contains(":memory:") {
+            true => 1,
+            false => 10,
+        };
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(connections)
+            .connect(&config.uri)
+            .await?;
+
+        Ok(SqlDatabase { pool, name })
+    }
+}
+
+fn millis(date: DateTime<Utc>) -> i64 {
+    date.timestamp_millis()
+}
+
+fn date(row: &AnyRow, column: &str) -> DatabaseResult<DateTime<Utc>> {
+    let millis: i64 = row.try_get(column)?;
+
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .ok_or_else(|| invalid(format!("invalid timestamp in {}", column)))
+}
+
+fn bson_date(row: &AnyRow, column: &str) -> DatabaseResult<Option<bson::DateTime>> {
+    let millis: Option<i64> = row.try_get(column)?;
+    Ok(millis.map(bson::DateTime::from_millis))
+}
+
+fn object_id(row: &AnyRow, column: &str) -> DatabaseResult<ObjectId> {
+    let hex: String = row.try_get(column)?;
+    ObjectId::parse_str(hex).map_err(invalid)
+}
+
+fn flag(row: &AnyRow, column: &str) -> DatabaseResult<bool> {
+    Ok(row.try_get::<i64, _>(column)? != 0)
+}
+
+/// Nested values such as vaults are stored as JSON text.
+fn json<T: DeserializeOwned>(row: &AnyRow, column: &str) -> DatabaseResult<Option<T>> {
+    let text: Option<String> = row.try_get(column)?;
+
+    match text {
+        Some(text) => Ok(Some(serde_json::from_str(&text)?)),
+        None => Ok(None),
+    }
+}
+
+fn to_json<T: Serialize>(value: Option<&T>) -> DatabaseResult<Option<String>> {
+    match value {
+        Some(value) => Ok(Some(serde_json::to_string(value)?)),
+        None => Ok(None),
+    }
+}
+
+fn user(row: &AnyRow) -> DatabaseResult<User> {
+    Ok(User {
+        _id: object_id(row, "_id")?,
+        username: row.try_get("username")?,
+        email: row.try_get("email")?,
+        password: row.try_get("password")?,
+        quota: UserQuota {
+            used: row.try_get("quota_used")?,
+            available: row.try_get("quota_available")?,
+        },
+        role: row.try_get("role")?,
+        token: row.try_get("token")?,
+        suspended: flag(row, "suspended")?,
+        vault: json(row, "vault")?,
+        slug: json(row, "slug")?,
+        created_at: date(row, "created_at")?,
+        updated_at: date(row, "updated_at")?,
+    })
+}
+
+fn file(row: &AnyRow) -> DatabaseResult<File> {
+    let compression: i64 = row.try_get("compression")?;
+
+    Ok(File {
+        _id: object_id(row, "_id")?,
+        id: row.try_get("id")?,
+        filename: row.try_get("filename")?,
+        mimetype: row.try_get("mimetype")?,
+        uploader: object_id(row, "uploader")?,
+        hash: row.try_get("hash")?,
+        dkey: row.try_get("dkey")?,
+        size: row.try_get("size")?,
+        blob: row.try_get("blob")?,
+        created_at: date(row, "created_at")?,
+        expires_at: bson_date(row, "expires_at")?,
+        purged: flag(row, "purged")?,
+        max_downloads: row.try_get("max_downloads")?,
+        downloads: row.try_get("downloads")?,
+        lock: json(row, "lock")?,
+        sealed: flag(row, "sealed")?,
+        vault_key: row.try_get("vault_key")?,
+        compression: u8::try_from(compression)
+            .ok()
+            .and_then(Compression::from_id)
+            .ok_or_else(|| invalid("unknown compression"))?,
+    })
+}
+
+fn token(row: &AnyRow) -> DatabaseResult<ApiToken> {
+    let scopes: i64 = row.try_get("scopes")?;
+
+    Ok(ApiToken {
+        _id: object_id(row, "_id")?,
+        user: object_id(row, "user_id")?,
+        name: row.try_get("name")?,
+        hash: row.try_get("hash")?,
+        scopes: Scopes::from_bits_truncate(scopes as u32),
+        created_at: date(row, "created_at")?,
+        last_used_at: bson_date(row, "last_used_at")?,
+        expires_at: bson_date(row, "expires_at")?,
+    })
+}
+
+fn role(row: &AnyRow) -> DatabaseResult<Role> {
+    let permissions: i64 = row.try_get("permissions")?;
+
+    Ok(Role {
+        _id: row.try_get("_id")?,
+        permissions: Permissions::from_bits_truncate(permissions as u32),
+        max_file_lifetime: row.try_get("max_file_lifetime")?,
+        created_at: date(row, "created_at")?,
+        updated_at: date(row, "updated_at")?,
+    })
+}
+
+fn invite(row: &AnyRow) -> DatabaseResult<Invite> {
+    Ok(Invite {
+        _id: object_id(row, "_id")?,
+        hash: row.try_get("hash")?,
+        created_by: object_id(row, "created_by")?,
+        uses: row.try_get("uses")?,
+        max_uses: row.try_get("max_uses")?,
+        role: row.try_get("role")?,
+        quota: row.try_get("quota")?,
+        created_at: date(row, "created_at")?,
+        expires_at: bson_date(row, "expires_at")?,
+    })
+}
+
+fn all<T>(rows: Vec<AnyRow>, map: fn(&AnyRow) -> DatabaseResult<T>) -> DatabaseResult<Vec<T>> {
+    rows.iter().map(map).collect()
+}
+
+/// Escapes `text` so it can be used as a literal inside a `LIKE` pattern.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\%_".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+const USER_FILTER: &str = "($1 IS NULL OR lower(username) LIKE $1 ESCAPE '\\')
+    AND ($2 IS NULL OR lower(email) LIKE $2 ESCAPE '\\')
+    AND ($3 IS NULL OR suspended = $3)
+    AND ($4 IS NULL OR role = $4)";
+
+#[async_trait]
+impl UserRepository for SqlDatabase {
+    async fn count_users(&self) -> DatabaseResult<u64> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.try_get::<i64, _>("count")? as u64)
+    }
+
+    async fn claim_bootstrap(&self) -> DatabaseResult<bool> {
+        let result = sqlx::query(
+            "INSERT INTO meta (_id, created_at) VALUES ($1, $2) ON CONFLICT (_id) DO NOTHING",
+        )
+        .bind(BOOTSTRAP)
+        .bind(millis(Utc::now()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn release_bootstrap(&self) -> DatabaseResult<()> {
+        sqlx::query("DELETE FROM meta WHERE _id = $1")
+            .bind(BOOTSTRAP)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_user(&self, user: &User) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO users (_id, username, email, password, quota_used, quota_available, role,
+                token, suspended, vault, slug, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+        )
+        .bind(user._id.to_hex())
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.password)
+        .bind(user.quota.used)
+        .bind(user.quota.available)
+        .bind(&user.role)
+        .bind(&user.token)
+        .bind(user.suspended as i64)
+        .bind(to_json(user.vault.as_ref())?)
+        .bind(to_json(user.slug.as_ref())?)
+        .bind(millis(user.created_at))
+        .bind(millis(user.updated_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_user(&self, id: ObjectId) -> DatabaseResult<Option<User>> {
+        let row = sqlx::query("SELECT * FROM users WHERE _id = $1")
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user).transpose()
+    }
+
+    async fn find_user_by_token(&self, hash: &str) -> DatabaseResult<Option<User>> {
+        let row = sqlx::query("SELECT * FROM users WHERE token = $1")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user).transpose()
+    }
+
+    async fn find_user_by_login(&self, login: &str) -> DatabaseResult<Option<User>> {
+        let row = sqlx::query("SELECT * FROM users WHERE username = $1 OR email = $1 LIMIT 1")
+            .bind(login)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(user).transpose()
+    }
+
+    async fn list_users(
+        &self,
+        filter: &UserListRequest,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<User>, u64)> {
+        let pattern = |text: &Option<String>| {
+            text.as_ref()
+                .map(|text| format!("%{}%", escape_like(&text.to_lowercase())))
+        };
+
+        let (username, email) = (pattern(&filter.username), pattern(&filter.email));
+        let suspended = filter.suspended.map(i64::from);
+
+        let total = sqlx::query(&format!(
+            "SELECT COUNT(*) AS count FROM users WHERE {}",
+            USER_FILTER
+        ))
+        .bind(username.clone())
+        .bind(email.clone())
+        .bind(suspended)
+        .bind(filter.role.clone())
+        .fetch_one(&self.pool)
+        .await?
+        .try_get::<i64, _>("count")?;
+
+        let rows = sqlx::query(&format!(
+            "SELECT * FROM users WHERE {} ORDER BY created_at LIMIT $5 OFFSET $6",
+            USER_FILTER
+        ))
+        .bind(username)
+        .bind(email)
+        .bind(suspended)
+        .bind(filter.role.clone())
+        .bind(limit as i64)
+        .bind(skip as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((all(rows, user)?, total as u64))
+    }
+
+    async fn count_users_with_role(&self, role: &str) -> DatabaseResult<u64> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM users WHERE role = $1")
+            .bind(role)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.try_get::<i64, _>("count")? as u64)
+    }
+
+    async fn update_user(&self, id: ObjectId, update: &UserUpdateRequest) -> DatabaseResult<()> {
+        sqlx::query(
+            "UPDATE users SET email = COALESCE($1, email), role = COALESCE($2, role),
+                quota_available = COALESCE($3, quota_available), slug = COALESCE($4, slug),
+                updated_at = $5
+            WHERE _id = $6",
+        )
+        .bind(update.email.clone())
+        .bind(update.role.clone())
+        .bind(update.quota)
+        .bind(to_json(update.slug.as_ref())?)
+        .bind(millis(Utc::now()))
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_suspended(&self, id: ObjectId, suspended: bool) -> DatabaseResult<()> {
+        sqlx::query("UPDATE users SET suspended = $1, updated_at = $2 WHERE _id = $3")
+            .bind(suspended as i64)
+            .bind(millis(Utc::now()))
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_token(&self, id: ObjectId, hash: &str) -> DatabaseResult<()> {
+        sqlx::query("UPDATE users SET token = $1, updated_at = $2 WHERE _id = $3")
+            .bind(hash)
+            .bind(millis(Utc::now()))
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn replace_password(
+        &self,
+        id: ObjectId,
+        current: &str,
+        hash: &str,
+    ) -> DatabaseResult<()> {
+        sqlx::query(
+            "UPDATE users SET password = $1, updated_at = $2 WHERE _id = $3 AND password = $4",
+        )
+        .bind(hash)
+        .bind(millis(Utc::now()))
+        .bind(id.to_hex())
+        .bind(current)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_used_quota(&self, id: ObjectId, delta: i64) -> DatabaseResult<()> {
+        sqlx::query(
+            "UPDATE users SET quota_used = quota_used + $1, updated_at = $2 WHERE _id = $3",
+        )
+        .bind(delta)
+        .bind(millis(Utc::now()))
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn create_vault(&self, id: ObjectId, vault: &Vault) -> DatabaseResult<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET vault = $1, updated_at = $2 WHERE _id = $3 AND vault IS NULL",
+        )
+        .bind(to_json(Some(vault))?)
+        .bind(millis(Utc::now()))
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_vault(&self, id: ObjectId) -> DatabaseResult<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("UPDATE users SET vault = NULL, updated_at = $1 WHERE _id = $2")
+            .bind(millis(Utc::now()))
+            .bind(id.to_hex())
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query("UPDATE files SET vault_key = NULL WHERE uploader = $1")
+            .bind(id.to_hex())
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_user(&self, id: ObjectId) -> DatabaseResult<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        for statement in [
+            "DELETE FROM users WHERE _id = $1",
+            "DELETE FROM files WHERE uploader = $1",
+            "DELETE FROM tokens WHERE user_id = $1",
+        ] {
+            sqlx::query(statement)
+                .bind(id.to_hex())
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FileRepository for SqlDatabase {
+    async fn insert_file(&self, file: &File) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO files (_id, id, filename, mimetype, uploader, hash, dkey, size, blob,
+                created_at, expires_at, purged, max_downloads, downloads, lock, sealed, vault_key,
+                compression)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                $18)",
+        )
+        .bind(file._id.to_hex())
+        .bind(file.id.clone())
+        .bind(&file.filename)
+        .bind(&file.mimetype)
+        .bind(file.uploader.to_hex())
+        .bind(&file.hash)
+        .bind(&file.dkey)
+        .bind(file.size)
+        .bind(file.blob.clone())
+        .bind(millis(file.created_at))
+        .bind(file.expires_at.map(|date| date.timestamp_millis()))
+        .bind(file.purged as i64)
+        .bind(file.max_downloads)
+        .bind(file.downloads)
+        .bind(to_json(file.lock.as_ref())?)
+        .bind(file.sealed as i64)
+        .bind(file.vault_key.clone())
+        .bind(file.compression as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_file(&self, id: &str) -> DatabaseResult<Option<File>> {
+        let row =
+            sqlx::query("SELECT * FROM files WHERE id = $1 OR (hash = $1 AND id IS NULL) LIMIT 1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        row.as_ref().map(file).transpose()
+    }
+
+    async fn public_id_exists(&self, id: &str) -> DatabaseResult<bool> {
+        let row = sqlx::query("SELECT _id FROM files WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn delete_file(&self, id: ObjectId) -> DatabaseResult<Option<File>> {
+        let row = sqlx::query("DELETE FROM files WHERE _id = $1 RETURNING *")
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(file).transpose()
+    }
+
+    async fn claim_download(&self, id: ObjectId) -> DatabaseResult<Option<File>> {
+        let row = sqlx::query(
+            "UPDATE files SET downloads = downloads + 1
+            WHERE _id = $1 AND purged = 0 AND downloads < max_downloads
+            RETURNING *",
+        )
+        .bind(id.to_hex())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(file).transpose()
+    }
+
+    async fn mark_purged(&self, id: ObjectId) -> DatabaseResult<bool> {
+        let result = sqlx::query("UPDATE files SET purged = 1 WHERE _id = $1 AND purged = 0")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn expired_files(&self, now: DateTime<Utc>) -> DatabaseResult<Vec<File>> {
+        let rows = sqlx::query("SELECT * FROM files WHERE expires_at <= $1 AND purged = 0")
+            .bind(millis(now))
+            .fetch_all(&self.pool)
+            .await?;
+
+        all(rows, file)
+    }
+
+    async fn forget_purged(&self, before: DateTime<Utc>) -> DatabaseResult<u64> {
+        let result = sqlx::query("DELETE FROM files WHERE purged = 1 AND expires_at <= $1")
+            .bind(millis(before))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn file_totals(&self) -> DatabaseResult<(i64, i64)> {
+        //? PostgreSQL sums BIGINTs into a NUMERIC
+        let row = sqlx::query(
+            "SELECT COUNT(*) AS count, CAST(COALESCE(SUM(size), 0) AS BIGINT) AS size
+            FROM files WHERE purged = 0",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((row.try_get("count")?, row.try_get("size")?))
+    }
+
+    async fn list_vault_files(
+        &self,
+        uploader: ObjectId,
+        skip: u64,
+        limit: u64,
+    ) -> DatabaseResult<(Vec<File>, u64)> {
+        const FILTER: &str = "uploader = $1 AND vault_key IS NOT NULL AND purged = 0";
+
+        let total = sqlx::query(&format!(
+            "SELECT COUNT(*) AS count FROM files WHERE {}",
+            FILTER
+        ))
+        .bind(uploader.to_hex())
+        .fetch_one(&self.pool)
+        .await?
+        .try_get::<i64, _>("count")?;
+
+        let rows = sqlx::query(&format!(
+            "SELECT * FROM files WHERE {} ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+            FILTER
+        ))
+        .bind(uploader.to_hex())
+        .bind(limit as i64)
+        .bind(skip as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((all(rows, file)?, total as u64))
+    }
+}
+
+#[async_trait]
+impl TokenRepository for SqlDatabase {
+    async fn insert_token(&self, token: &ApiToken) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO tokens (_id, user_id, name, hash, scopes, created_at, last_used_at,
+                expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(token._id.to_hex())
+        .bind(token.user.to_hex())
+        .bind(&token.name)
+        .bind(&token.hash)
+        .bind(token.scopes.bits() as i64)
+        .bind(millis(token.created_at))
+        .bind(token.last_used_at.map(|date| date.timestamp_millis()))
+        .bind(token.expires_at.map(|date| date.timestamp_millis()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_token(&self, hash: &str) -> DatabaseResult<Option<ApiToken>> {
+        let row = sqlx::query("SELECT * FROM tokens WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(token).transpose()
+    }
+
+    async fn list_tokens(&self, user: ObjectId) -> DatabaseResult<Vec<ApiToken>> {
+        let rows = sqlx::query("SELECT * FROM tokens WHERE user_id = $1 ORDER BY created_at")
+            .bind(user.to_hex())
+            .fetch_all(&self.pool)
+            .await?;
+
+        all(rows, token)
+    }
+
+    async fn touch_token(&self, id: ObjectId) -> DatabaseResult<()> {
+        sqlx::query("UPDATE tokens SET last_used_at = $1 WHERE _id = $2")
+            .bind(millis(Utc::now()))
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_token_hash(&self, id: ObjectId, hash: &str) -> DatabaseResult<()> {
+        sqlx::query("UPDATE tokens SET hash = $1 WHERE _id = $2")
+            .bind(hash)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_token(&self, id: ObjectId, user: ObjectId) -> DatabaseResult<bool> {
+        let result = sqlx::query("DELETE FROM tokens WHERE _id = $1 AND user_id = $2")
+            .bind(id.to_hex())
+            .bind(user.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn forget_expired_tokens(&self, now: DateTime<Utc>) -> DatabaseResult<u64> {
+        let result = sqlx::query("DELETE FROM tokens WHERE expires_at <= $1")
+            .bind(millis(now))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for SqlDatabase {
+    async fn find_role(&self, name: &str) -> DatabaseResult<Option<Role>> {
+        let row = sqlx::query("SELECT * FROM roles WHERE _id = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.as_ref().map(role).transpose()
+    }
+
+    async fn list_roles(&self) -> DatabaseResult<Vec<Role>> {
+        let rows = sqlx::query("SELECT * FROM roles ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+
+        all(rows, role)
+    }
+
+    async fn put_role(
+        &self,
+        name: &str,
+        permissions: Permissions,
+        max_file_lifetime: Option<i64>,
+    ) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO roles (_id, permissions, max_file_lifetime, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $4)
+            ON CONFLICT (_id) DO UPDATE SET permissions = excluded.permissions,
+                max_file_lifetime = excluded.max_file_lifetime, updated_at = excluded.updated_at",
+        )
+        .bind(name)
+        .bind(permissions.bits() as i64)
+        .bind(max_file_lifetime)
+        .bind(millis(Utc::now()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn ensure_role(&self, name: &str, permissions: Permissions) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO roles (_id, permissions, created_at, updated_at) VALUES ($1, $2, $3, $3)
+            ON CONFLICT (_id) DO NOTHING",
+        )
+        .bind(name)
+        .bind(permissions.bits() as i64)
+        .bind(millis(Utc::now()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_role(&self, name: &str) -> DatabaseResult<bool> {
+        let result = sqlx::query("DELETE FROM roles WHERE _id = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl InviteRepository for SqlDatabase {
+    async fn insert_invite(&self, invite: &Invite) -> DatabaseResult<()> {
+        sqlx::query(
+            "INSERT INTO invites (_id, hash, created_by, uses, max_uses, role, quota, created_at,
+                expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(invite._id.to_hex())
+        .bind(&invite.hash)
+        .bind(invite.created_by.to_hex())
+        .bind(invite.uses)
+        .bind(invite.max_uses)
+        .bind(invite.role.clone())
+        .bind(invite.quota)
+        .bind(millis(invite.created_at))
+        .bind(invite.expires_at.map(|date| date.timestamp_millis()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_invites(&self) -> DatabaseResult<Vec<Invite>> {
+        let rows = sqlx::query("SELECT * FROM invites ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+
+        all(rows, invite)
+    }
+
+    async fn delete_invite(&self, id: ObjectId) -> DatabaseResult<bool> {
+        let result = sqlx::query("DELETE FROM invites WHERE _id = $1")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn redeem_invite(&self, hash: &str) -> DatabaseResult<Option<Invite>> {
+        let row = sqlx::query(
+            "UPDATE invites SET uses = uses + 1
+            WHERE hash = $1
+                AND (expires_at IS NULL OR expires_at > $2)
+                AND (max_uses IS NULL OR uses < max_uses)
+            RETURNING *",
+        )
+        .bind(hash)
+        .bind(millis(Utc::now()))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(invite).transpose()
+    }
+
+    async fn restore_invite(&self, id: ObjectId) -> DatabaseResult<()> {
+        sqlx::query("UPDATE invites SET uses = uses - 1 WHERE _id = $1")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatabaseBackend for SqlDatabase {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn setup(&self) -> DatabaseResult<()> {
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&self.pool).await?;
+        }
+
+        //? Installs that already have accounts are past their bootstrap, empty ones start over even
+        //? if the first registration never finished
+        match self.count_users().await? {
+            0 => self.release_bootstrap().await?,
+            _ => {
+                self.claim_bootstrap().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_sql_database() {
+    let mut configs = vec![DatabaseConfig {
+        kind: DatabaseKind::Sqlite,
+        uri: "sqlite::memory:".to_string(),
+        db_name: String::new(),
+    }];
+
+    //? Set to the URI of a scratch database to run the same checks against PostgreSQL, its
+    //? tables are dropped first
+    if let Ok(uri) = std::env::var("MGO_TEST_POSTGRES") {
+        configs.push(DatabaseConfig {
+            kind: DatabaseKind::Postgres,
+            uri,
+            db_name: String::new(),
+        });
+    }
+
+    for config in configs {
+        let database = SqlDatabase::connect(&config).await.unwrap();
+
+        for table in ["users", "files", "tokens", "roles", "invites", "meta"] {
+            sqlx::query(&format!("DROP TABLE IF EXISTS {}", table))
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+
+        database.setup().await.unwrap();
+        database.setup().await.unwrap();
+
+        //? A first registration that never finished does not lock out the next one
+        assert!(database.claim_bootstrap().await.unwrap());
+        database.setup().await.unwrap();
+        assert!(database.claim_bootstrap().await.unwrap());
+        database.release_bootstrap().await.unwrap();
+
+        let user = User::from("Alice", "hash", "alice@example.com", "token");
+        database.insert_user(&user).await.unwrap();
+
+        let taken = User::from("alice", "hash", "other@example.com", "other");
+        assert!(matches!(
+            database.insert_user(&taken).await,
+            Err(DatabaseError::Taken("username"))
+        ));
+
+        let vault = Vault {
+            public_key: "key".to_string(),
+            private_key: None,
+            created_at: Utc::now(),
+        };
+        assert!(database.create_vault(user._id, &vault).await.unwrap());
+        assert!(!database.create_vault(user._id, &vault).await.unwrap());
+
+        let filter = UserListRequest {
+            page: None,
+            per_page: None,
+            username: Some("LIC".to_string()),
+            email: None,
+            suspended: Some(false),
+            role: None,
+        };
+        let (found, total) = database.list_users(&filter, 0, 10).await.unwrap();
+        assert_eq!((found.len(), total), (1, 1));
+        assert_eq!(found[0].vault.as_ref().unwrap().public_key, "key");
+
+        //? Setting up a database that already has accounts closes the bootstrap
+        database.setup().await.unwrap();
+        assert!(!database.claim_bootstrap().await.unwrap());
+        database.release_bootstrap().await.unwrap();
+        assert!(database.claim_bootstrap().await.unwrap());
+        assert!(!database.claim_bootstrap().await.unwrap());
+
+        let file = |id: Option<&str>, hash: &str, max_downloads| File {
+            _id: ObjectId::new(),
+            id: id.map(str::to_string),
+            filename: "a.txt".to_string(),
+            mimetype: "text/plain".to_string(),
+            uploader: ObjectId::new(),
+            hash: hash.to_string(),
+            dkey: String::new(),
+            size: 10,
+            blob: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            purged: false,
+            max_downloads,
+            downloads: 0,
+            lock: None,
+            sealed: false,
+            vault_key: None,
+            compression: Compression::Zstd,
+        };
+
+        let limited = file(Some("abcd1234"), "same", Some(2));
+        let legacy = file(None, "legacy", None);
+        database.insert_file(&limited).await.unwrap();
+        database.insert_file(&legacy).await.unwrap();
+
+        //? Files uploaded before public IDs existed are still found by their hash
+        let found = database.find_file("abcd1234").await.unwrap().unwrap();
+        assert_eq!(found._id, limited._id);
+        assert_eq!(found.compression, Compression::Zstd);
+        assert_eq!(
+            database.find_file("legacy").await.unwrap().unwrap()._id,
+            legacy._id
+        );
+        assert!(database.find_file("same").await.unwrap().is_none());
+        assert!(database.public_id_exists("abcd1234").await.unwrap());
+        assert_eq!(database.file_totals().await.unwrap(), (2, 20));
+
+        //? Concurrent downloads never claim more than the limit
+        let claims =
+            futures_util::future::join_all((0..5).map(|_| database.claim_download(limited._id)))
+                .await;
+        let claimed: Vec<_> = claims.into_iter().filter_map(Result::unwrap).collect();
+        assert_eq!(claimed.len(), 2);
+        assert!(claimed.iter().any(|file| file.is_exhausted()));
+
+        assert!(database.mark_purged(legacy._id).await.unwrap());
+        assert!(!database.mark_purged(legacy._id).await.unwrap());
+        assert!(database.claim_download(legacy._id).await.unwrap().is_none());
+        assert_eq!(database.file_totals().await.unwrap(), (1, 10));
+
+        assert!(database.delete_file(limited._id).await.unwrap().is_some());
+        assert!(database.delete_file(limited._id).await.unwrap().is_none());
+
+        let user = ObjectId::new();
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+        let (old, _) = ApiToken::new(user, "old", Scopes::all(), Some(expired));
+        let (new, _) = ApiToken::new(user, "new", Scopes::UPLOAD, None);
+        let unrotated = new.hash.clone();
+        database.insert_token(&old).await.unwrap();
+        database.insert_token(&new).await.unwrap();
+
+        let found = database.find_token(&new.hash).await.unwrap().unwrap();
+        assert_eq!((found.scopes, found.last_used_at), (Scopes::UPLOAD, None));
+
+        database.touch_token(new._id).await.unwrap();
+        database.set_token_hash(new._id, "rotated").await.unwrap();
+        assert!(database.find_token(&unrotated).await.unwrap().is_none());
+
+        let found = database.find_token("rotated").await.unwrap().unwrap();
+        assert!(found.last_used_at.is_some());
+
+        assert_eq!(database.forget_expired_tokens(Utc::now()).await.unwrap(), 1);
+        assert_eq!(database.list_tokens(user).await.unwrap()[0].name, "new");
+
+        assert!(!database
+            .delete_token(new._id, ObjectId::new())
+            .await
+            .unwrap());
+        assert!(database.delete_token(new._id, user).await.unwrap());
+        assert!(database.list_tokens(user).await.unwrap().is_empty());
+
+        database
+            .ensure_role("editor", Permissions::UPLOAD)
+            .await
+            .unwrap();
+        database
+            .ensure_role("editor", Permissions::all())
+            .await
+            .unwrap();
+
+        let role = database.find_role("editor").await.unwrap().unwrap();
+        assert_eq!(
+            (role.permissions, role.max_file_lifetime),
+            (Permissions::UPLOAD, None)
+        );
+
+        database
+            .put_role("editor", Permissions::DELETE_OWN, Some(60))
+            .await
+            .unwrap();
+
+        let role = database.find_role("editor").await.unwrap().unwrap();
+        assert_eq!(
+            (role.permissions, role.max_file_lifetime),
+            (Permissions::DELETE_OWN, Some(60))
+        );
+        assert_eq!(database.list_roles().await.unwrap().len(), 1);
+        assert_eq!(database.count_users_with_role("editor").await.unwrap(), 0);
+
+        assert!(database.delete_role("editor").await.unwrap());
+        assert!(!database.delete_role("editor").await.unwrap());
+
+        let invite = |hash: &str, max_uses, expires_at| Invite {
+            _id: ObjectId::new(),
+            hash: hash.to_string(),
+            created_by: ObjectId::new(),
+            uses: 0,
+            max_uses,
+            role: Some("editor".to_string()),
+            quota: Some(1024),
+            created_at: Utc::now(),
+            expires_at,
+        };
+
+        let twice = invite("twice", Some(2), None);
+        let expired = invite("expired", None, Some(bson::DateTime::from_millis(0)));
+        database.insert_invite(&twice).await.unwrap();
+        database.insert_invite(&expired).await.unwrap();
+
+        let redeemed = database.redeem_invite("twice").await.unwrap().unwrap();
+        assert_eq!((redeemed.uses, redeemed.quota), (1, Some(1024)));
+        assert!(database.redeem_invite("twice").await.unwrap().is_some());
+        assert!(database.redeem_invite("twice").await.unwrap().is_none());
+
+        //? A failed registration gives its use back
+        database.restore_invite(twice._id).await.unwrap();
+        assert!(database.redeem_invite("twice").await.unwrap().is_some());
+
+        assert!(database.redeem_invite("expired").await.unwrap().is_none());
+        assert!(database.redeem_invite("unknown").await.unwrap().is_none());
+
+        assert_eq!(database.list_invites().await.unwrap().len(), 2);
+        assert!(database.delete_invite(expired._id).await.unwrap());
+        assert!(!database.delete_invite(expired._id).await.unwrap());
+    }
+}
@@ -0,0 +1,44 @@
+use rand::{rngs::OsRng, Rng};
+
+/// The characters that public file IDs are made of unless configured otherwise.
+pub const DEFAULT_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Generates a random public ID of `length` characters drawn from `alphabet`.
+pub fn generate_id(length: usize, alphabet: &str) -> String {
+    let alphabet: Vec<char> = alphabet.chars().collect();
+    let mut rng = OsRng;
+
+    (0..length)
+        .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+        .collect()
+}
+
+/// Checks that IDs drawn from `alphabet` can be used as a URL path segment.
+///
+/// Dots are rejected because anything after the first one is treated as a file extension.
+pub fn validate_alphabet(alphabet: &str) -> Result<(), &'static str> {
+    if alphabet.is_empty() {
+        return Err("The file ID alphabet must not be empty");
+    }
+
+    if !alphabet
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("The file ID alphabet may only contain ASCII letters, digits, '-' and '_'");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_generate_id() {
+    let id = generate_id(12, "ab");
+
+    assert_eq!(id.len(), 12);
+    assert!(id.chars().all(|c| c == 'a' || c == 'b'));
+
+    assert!(validate_alphabet(DEFAULT_ALPHABET).is_ok());
+    assert!(validate_alphabet("").is_err());
+    assert!(validate_alphabet("abc.").is_err());
+}
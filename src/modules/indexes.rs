@@ -0,0 +1,117 @@
+//! MongoDB indexes created on startup, the unique ones back the checks in the route handlers.
+
+use bson::{doc, Document};
+use mongodb::{
+    error::{Error, ErrorKind, WriteFailure},
+    options::{Collation, CollationStrength, IndexOptions},
+    Database, IndexModel,
+};
+
+/// Name of the unique index on `users.username`, which ignores case.
+pub const USERNAME_INDEX: &str = "username_unique";
+
+/// Name of the unique index on `users.email`, which ignores case.
+pub const EMAIL_INDEX: &str = "email_unique";
+
+/// Server error code for a duplicate key in a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+fn unique(keys: Document, name: &str) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(
+            IndexOptions::builder()
+                .name(name.to_string())
+                .unique(true)
+                .build(),
+        )
+        .build()
+}
+
+fn unique_ignoring_case(keys: Document, name: &str) -> IndexModel {
+    let collation = Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build();
+
+    IndexModel::builder()
+        .keys(keys)
+        .options(
+            IndexOptions::builder()
+                .name(name.to_string())
+                .unique(true)
+                .collation(collation)
+                .build(),
+        )
+        .build()
+}
+
+/// Creates the indexes that do not exist yet, fails if existing data violates a unique index.
+pub async fn setup(database: &Database) -> mongodb::error::Result<()> {
+    database
+        .collection::<Document>("users")
+        .create_indexes(
+            [
+                unique_ignoring_case(doc! {"username": 1}, USERNAME_INDEX),
+                unique_ignoring_case(doc! {"email": 1}, EMAIL_INDEX),
+                unique(doc! {"token": 1}, "token_unique"),
+            ],
+            None,
+        )
+        .await?;
+
+    database
+        .collection::<Document>("tokens")
+        .create_indexes(
+            [
+                unique(doc! {"hash": 1}, "hash_unique"),
+                IndexModel::builder().keys(doc! {"user": 1}).build(),
+            ],
+            None,
+        )
+        .await?;
+
+    database
+        .collection::<Document>("invites")
+        .create_index(unique(doc! {"hash": 1}, "hash_unique"), None)
+        .await?;
+
+    //? Files uploaded before public IDs existed have no `id`
+    let public_id = IndexModel::builder()
+        .keys(doc! {"id": 1})
+        .options(
+            IndexOptions::builder()
+                .name("id_unique".to_string())
+                .unique(true)
+                .partial_filter_expression(doc! {"id": {"$type": "string"}})
+                .build(),
+        )
+        .build();
+
+    database
+        .collection::<Document>("files")
+        .create_indexes(
+            [
+                public_id,
+                IndexModel::builder().keys(doc! {"expires_at": 1}).build(),
+            ],
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// The name of the unique index `error` was caused by, if it is a duplicate key error.
+pub fn duplicate_index(error: &Error) -> Option<&'static str> {
+    match *error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(ref failure))
+            if failure.code == DUPLICATE_KEY =>
+        {
+            [USERNAME_INDEX, EMAIL_INDEX]
+                .into_iter()
+                .find(|index| failure.message.contains(index))
+        }
+        _ => None,
+    }
+}
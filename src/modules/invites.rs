@@ -0,0 +1,15 @@
+//! Redeeming invite codes for registration.
+
+use super::{
+    database::{DatabaseBackend, DatabaseResult},
+    hashing::hash_string,
+};
+use crate::structs::invites::Invite;
+
+/// Uses up one redemption of the invite with the given code.
+///
+/// Returns `None` if the code is unknown, expired or has no uses left. The check and the
+/// increment happen in one update so concurrent registrations can not exceed `max_uses`.
+pub async fn redeem(database: &dyn DatabaseBackend, code: &str) -> DatabaseResult<Option<Invite>> {
+    database.redeem_invite(&hash_string(code)).await
+}
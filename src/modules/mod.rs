@@ -1,4 +1,17 @@
+pub mod compression;
 pub mod config;
+pub mod container;
 pub mod crypto;
+pub mod database;
 pub mod hashing;
+pub mod ids;
+pub mod indexes;
+pub mod invites;
+pub mod passphrases;
+pub mod passwords;
+pub mod roles;
+pub mod slugs;
 pub mod storage;
+pub mod sweeper;
+pub mod validation;
+pub mod vault;
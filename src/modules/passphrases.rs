@@ -0,0 +1,110 @@
+//! Passphrase protection of uploaded files.
+//!
+//! The key a protected file is encrypted with never appears in its link. It is wrapped with a
+//! key derived by Argon2id from the passphrase, with the key from the link as the Argon2 secret,
+//! so neither the link nor the passphrase alone is enough to decrypt the file.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bytes::{Bytes, BytesMut};
+use rand::{rngs::OsRng, Rng};
+
+use super::{
+    config::PasswordConfig,
+    crypto::{decrypt_bytes, encrypt_bytes, EncryptionKey},
+    storage::StorageError,
+};
+use crate::structs::files::PassphraseLock;
+
+/// Header API clients send the passphrase in, when uploading as well as when downloading.
+pub const PASSPHRASE_HEADER: &str = "X-Passphrase";
+
+fn derive(
+    passphrase: &str,
+    secret: &[u8],
+    salt: &[u8],
+    params: Params,
+) -> Result<Vec<u8>, argon2::Error> {
+    let mut key = vec![0u8; 32];
+
+    Argon2::new_with_secret(secret, Algorithm::Argon2id, Version::V0x13, params)?
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)?;
+
+    Ok(key)
+}
+
+/// Wraps `key` with a key derived from `passphrase` and `secret`, using the configured costs.
+pub fn lock(
+    passphrase: &str,
+    secret: &[u8],
+    key: &[u8],
+    config: &PasswordConfig,
+) -> Result<PassphraseLock, StorageError> {
+    let mut rng = OsRng;
+    let salt: [u8; 16] = rng.gen();
+    let nonce: [u8; 12] = rng.gen();
+
+    let params = Params::new(
+        config.memory_cost,
+        config.time_cost,
+        config.parallelism,
+        None,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let wrapping = EncryptionKey {
+        key: derive(passphrase, secret, &salt, params).map_err(|e| e.to_string())?,
+        nonce: nonce.to_vec(),
+    };
+
+    let wrapped = encrypt_bytes(&wrapping, &BytesMut::from(key)).map_err(|e| e.to_string())?;
+
+    Ok(PassphraseLock {
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        key: base64::encode(wrapped),
+        memory_cost: config.memory_cost,
+        time_cost: config.time_cost,
+        parallelism: config.parallelism,
+    })
+}
+
+/// Recovers the key wrapped by [`lock`], `None` if the passphrase or secret is wrong.
+pub fn unlock(passphrase: &str, secret: &[u8], lock: &PassphraseLock) -> Option<Vec<u8>> {
+    //? Files keep the costs they were locked with, so changing the config never locks them out
+    let params = Params::new(lock.memory_cost, lock.time_cost, lock.parallelism, None).ok()?;
+
+    let wrapping = EncryptionKey {
+        key: derive(
+            passphrase,
+            secret,
+            &base64::decode(&lock.salt).ok()?,
+            params,
+        )
+        .ok()?,
+        nonce: base64::decode(&lock.nonce).ok()?,
+    };
+
+    let key = decrypt_bytes(&wrapping, &Bytes::from(base64::decode(&lock.key).ok()?)).ok()?;
+
+    Some(key.to_vec())
+}
+
+#[test]
+fn test_passphrase_lock() {
+    let config = PasswordConfig {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+        ..PasswordConfig::default()
+    };
+
+    let key = [7u8; 32];
+    let locked = lock("correct horse", b"link key", &key, &config).unwrap();
+
+    assert_eq!(
+        unlock("correct horse", b"link key", &locked),
+        Some(key.to_vec())
+    );
+    assert_eq!(unlock("wrong horse", b"link key", &locked), None);
+    assert_eq!(unlock("correct horse", b"other key", &locked), None);
+}
@@ -0,0 +1,182 @@
+//! Argon2id password hashing.
+//!
+//! Passwords used to be stored as a single unsalted SHA3-512 digest, those hashes are still
+//! accepted and transparently replaced with an Argon2id hash the next time they are verified.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use rand::rngs::OsRng;
+
+use super::{
+    config::PasswordConfig, database::DatabaseBackend, hashing::hash_string, storage::StorageError,
+};
+use crate::structs::users::User;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordMatch {
+    Invalid,
+    Valid,
+    /// The password is correct but its hash is legacy SHA3 or uses outdated parameters.
+    NeedsRehash,
+}
+
+fn argon2(config: &PasswordConfig) -> Result<Argon2<'static>, argon2::Error> {
+    let params = Params::new(
+        config.memory_cost,
+        config.time_cost,
+        config.parallelism,
+        None,
+    )?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Checks that the configured cost parameters are accepted by Argon2.
+pub fn validate(config: &PasswordConfig) -> Result<(), argon2::Error> {
+    argon2(config).map(|_| ())
+}
+
+/// Hashes `password` into an Argon2id PHC string.
+pub fn hash_password(
+    password: &str,
+    config: &PasswordConfig,
+) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2(config)?.hash_password(password.as_bytes(), &salt)?;
+
+    Ok(hash.to_string())
+}
+
+/// Verifies `password` against a stored Argon2id PHC string or legacy SHA3-512 digest.
+pub fn verify_password(password: &str, stored: &str, config: &PasswordConfig) -> PasswordMatch {
+    let hash = match PasswordHash::new(stored) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return match constant_time_eq(hash_string(password).as_bytes(), stored.as_bytes()) {
+                true => PasswordMatch::NeedsRehash,
+                false => PasswordMatch::Invalid,
+            };
+        }
+    };
+
+    //? Verification uses the parameters recorded in the hash, not the configured ones
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return PasswordMatch::Invalid;
+    }
+
+    let current = match Params::try_from(&hash) {
+        Ok(params) => {
+            hash.algorithm == Algorithm::Argon2id.ident()
+                && params.m_cost() == config.memory_cost
+                && params.t_cost() == config.time_cost
+                && params.p_cost() == config.parallelism
+        }
+        Err(_) => false,
+    };
+
+    match current {
+        true => PasswordMatch::Valid,
+        false => PasswordMatch::NeedsRehash,
+    }
+}
+
+/// Verifies a user's password, upgrading the stored hash when it is outdated.
+///
+/// Hashing is deliberately slow, so it runs on the blocking thread pool.
+pub async fn authenticate(
+    database: &dyn DatabaseBackend,
+    config: &PasswordConfig,
+    user: &User,
+    password: &str,
+) -> Result<bool, StorageError> {
+    let (config, stored, password) = (config.clone(), user.password.clone(), password.to_string());
+
+    let rehashed =
+        tokio::task::spawn_blocking(move || match verify_password(&password, &stored, &config) {
+            PasswordMatch::Invalid => Ok(None),
+            PasswordMatch::Valid => Ok(Some(None)),
+            PasswordMatch::NeedsRehash => hash_password(&password, &config).map(|h| Some(Some(h))),
+        })
+        .await?
+        .map_err(|e| e.to_string())?;
+
+    match rehashed {
+        None => Ok(false),
+        Some(None) => Ok(true),
+        Some(Some(hash)) => {
+            //? Only replace the hash that was verified, in case the password changed meanwhile
+            database
+                .replace_password(user._id, &user.password, &hash)
+                .await?;
+
+            Ok(true)
+        }
+    }
+}
+
+/// Spends as long as verifying a password would, so that logins with an unknown username can
+/// not be told apart from ones with a wrong password by their timing.
+pub async fn authenticate_unknown(
+    config: &PasswordConfig,
+    password: &str,
+) -> Result<(), StorageError> {
+    let (config, password) = (config.clone(), password.to_string());
+
+    //? Hashing with the configured parameters costs the same as verifying a current hash
+    tokio::task::spawn_blocking(move || hash_password(&password, &config))
+        .await?
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[test]
+fn test_passwords() {
+    let config = PasswordConfig {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+        ..PasswordConfig::default()
+    };
+
+    let hash = hash_password("hunter2", &config).unwrap();
+
+    assert!(hash.starts_with("$argon2id$"));
+    assert_eq!(
+        verify_password("hunter2", &hash, &config),
+        PasswordMatch::Valid
+    );
+    assert_eq!(
+        verify_password("hunter3", &hash, &config),
+        PasswordMatch::Invalid
+    );
+
+    let stronger = PasswordConfig {
+        time_cost: 2,
+        ..config.clone()
+    };
+    assert_eq!(
+        verify_password("hunter2", &hash, &stronger),
+        PasswordMatch::NeedsRehash
+    );
+
+    //? Hashes stored before Argon2id was introduced
+    let legacy = hash_string("hunter2");
+    assert_eq!(
+        verify_password("hunter2", &legacy, &config),
+        PasswordMatch::NeedsRehash
+    );
+    assert_eq!(
+        verify_password("hunter3", &legacy, &config),
+        PasswordMatch::Invalid
+    );
+}
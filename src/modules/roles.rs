@@ -0,0 +1,37 @@
+//! Roles group permissions under a name and are assigned to users.
+//!
+//! The built-in roles are created on startup if they are missing, after that they are ordinary
+//! records that administrators may change.
+
+use super::database::{DatabaseBackend, DatabaseResult};
+use crate::structs::Permissions;
+
+/// Role given to newly created users.
+pub const DEFAULT_ROLE: &str = "user";
+
+/// Role with every permission, given to users who had the old `ADMIN` privilege.
+pub const ADMIN_ROLE: &str = "admin";
+
+pub fn builtin_roles() -> [(&'static str, Permissions); 4] {
+    [
+        (ADMIN_ROLE, Permissions::all()),
+        (
+            "moderator",
+            Permissions::UPLOAD
+                | Permissions::DELETE_OWN
+                | Permissions::DELETE_ANY
+                | Permissions::MODERATE,
+        ),
+        ("auditor", Permissions::VIEW_STATS),
+        (DEFAULT_ROLE, Permissions::UPLOAD | Permissions::DELETE_OWN),
+    ]
+}
+
+/// Creates the built-in roles that do not exist yet.
+pub async fn setup(database: &dyn DatabaseBackend) -> DatabaseResult<()> {
+    for (name, permissions) in builtin_roles() {
+        database.ensure_role(name, permissions).await?;
+    }
+
+    Ok(())
+}
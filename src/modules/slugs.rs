@@ -0,0 +1,146 @@
+//! Alternative spellings of public file IDs in links.
+//!
+//! A slug encodes the stored ID one character at a time, so every scheme can be decoded back to
+//! it without a lookup table in the database. IDs only ever contain the 64 characters allowed by
+//! [`validate_alphabet`](super::ids::validate_alphabet), which every scheme has a symbol for.
+
+use serde::{Deserialize, Serialize};
+
+/// The characters a public ID can be made of, in the order every scheme assigns its symbols.
+const ID_CHARS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Invisible characters, three of them spell one ID character in base 4.
+const ZERO_WIDTH: [char; 4] = ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}'];
+
+/// Single code point emoji, so no variation selectors or joiners end up in links.
+const EMOJI: &str = "🐀🐁🐂🐃🐄🐅🐆🐇🐈🐉🐊🐋🐌🐍🐎🐏🐐🐑🐒🐓🐔🐕🐖🐗🐘🐙🐚🐛🐜🐝🐞🐟🐠🐡🐢🐣🐤🐥🐦🐧🐨🐩🐪🐫🐬🐭🐮🐯🐰🐱🐲🐳🐴🐵🐶🐷🐸🐹🐺🐻🐼🐽🐾🦊";
+
+/// Joined in CamelCase, so the capitals mark where each word starts.
+const WORDS: [&str; 64] = [
+    "apple", "brave", "cedar", "daisy", "eagle", "fable", "giant", "hazel", "ivory", "jolly",
+    "koala", "lemon", "maple", "noble", "olive", "pearl", "quiet", "raven", "sunny", "tiger",
+    "umber", "vivid", "willow", "xenon", "yonder", "zebra", "amber", "bison", "coral", "dune",
+    "ember", "frost", "glade", "heron", "iris", "jade", "kestrel", "lotus", "meadow", "nectar",
+    "orchid", "pine", "quartz", "river", "stone", "thistle", "tulip", "velvet", "walnut", "yarrow",
+    "zephyr", "acorn", "breeze", "clover", "delta", "echo", "fern", "grove", "harbor", "island",
+    "juniper", "lark", "marble", "nova",
+];
+
+/// How the public ID of a file is spelled in its link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Slug {
+    /// The ID itself, random characters from the configured alphabet.
+    #[default]
+    Random,
+    /// Invisible zero-width characters.
+    ZeroWidth,
+    /// One emoji per character.
+    Emoji,
+    /// One capitalised word per character, e.g. `BraveRiverStone`.
+    Words,
+}
+
+fn index(c: char) -> Option<usize> {
+    ID_CHARS.chars().position(|id_char| id_char == c)
+}
+
+fn id_char(index: usize) -> Option<char> {
+    ID_CHARS.chars().nth(index)
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// Spells `id` with the given scheme, `None` if it has characters no public ID can contain.
+pub fn encode(slug: Slug, id: &str) -> Option<String> {
+    let indices = id.chars().map(index).collect::<Option<Vec<usize>>>()?;
+
+    Some(match slug {
+        Slug::Random => id.to_string(),
+        Slug::ZeroWidth => indices
+            .iter()
+            .flat_map(|i| [i >> 4, (i >> 2) & 3, i & 3].map(|digit| ZERO_WIDTH[digit]))
+            .collect(),
+        Slug::Emoji => {
+            let emoji: Vec<char> = EMOJI.chars().collect();
+            indices.iter().map(|&i| emoji[i]).collect()
+        }
+        Slug::Words => indices.iter().map(|&i| capitalize(WORDS[i])).collect(),
+    })
+}
+
+fn decode_zero_width(slug: &str) -> Option<String> {
+    let digits = slug
+        .chars()
+        .map(|c| ZERO_WIDTH.iter().position(|&zero_width| zero_width == c))
+        .collect::<Option<Vec<usize>>>()?;
+
+    if digits.is_empty() || digits.len() % 3 != 0 {
+        return None;
+    }
+
+    digits
+        .chunks(3)
+        .map(|digits| id_char(digits[0] << 4 | digits[1] << 2 | digits[2]))
+        .collect()
+}
+
+fn decode_emoji(slug: &str) -> Option<String> {
+    slug.chars()
+        .map(|c| EMOJI.chars().position(|emoji| emoji == c).and_then(id_char))
+        .collect()
+}
+
+fn decode_words(slug: &str) -> Option<String> {
+    if !slug.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return None;
+    }
+
+    let mut id = String::new();
+    let mut rest = slug;
+
+    while !rest.is_empty() {
+        let end = rest[1..]
+            .find(|c: char| c.is_ascii_uppercase())
+            .map_or(rest.len(), |end| end + 1);
+        let word = rest[..end].to_ascii_lowercase();
+
+        id.push(id_char(WORDS.iter().position(|&known| known == word)?)?);
+        rest = &rest[end..];
+    }
+
+    Some(id)
+}
+
+/// Turns the ID segment of a link back into the stored ID, whichever scheme it was spelled with.
+pub fn decode(slug: &str) -> String {
+    //? Random IDs could pass as words if every capital starts a listed word, which is why
+    //? lookups try the literal ID before decoding it
+    decode_zero_width(slug)
+        .or_else(|| decode_emoji(slug))
+        .or_else(|| decode_words(slug))
+        .unwrap_or_else(|| slug.to_string())
+}
+
+#[test]
+fn test_slugs() {
+    let id = "aZ09-_Qx";
+
+    for slug in [Slug::Random, Slug::ZeroWidth, Slug::Emoji, Slug::Words] {
+        let encoded = encode(slug, id).unwrap();
+        assert_eq!(decode(&encoded), id);
+    }
+
+    assert_eq!(encode(Slug::Words, "Bb").unwrap(), "BraveBison");
+    assert_eq!(encode(Slug::ZeroWidth, "A").unwrap().chars().count(), 3);
+    assert_eq!(encode(Slug::Emoji, "a.b"), None);
+    assert_eq!(decode("abcd1234"), "abcd1234");
+    assert_eq!(decode("BraveUnknown"), "BraveUnknown");
+}
@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, TryStreamExt};
+use std::io::ErrorKind;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio_util::io::ReaderStream;
+
+use super::{not_found, ByteStream, ObjectMeta, StorageBackend, StorageResult};
+
+/// Stores objects as files below a root directory, one file per key.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> LocalStorage {
+        LocalStorage {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn key(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let parts = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(parts.join("/"))
+    }
+}
+
+impl Default for LocalStorage {
+    fn default() -> Self {
+        LocalStorage::new("data")
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn put(&self, key: &str, bytes: Bytes) -> StorageResult<()> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = tokio::fs::File::create(path).await?;
+        file.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Bytes> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => Ok(Bytes::from(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(not_found(key)),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(not_found(key)),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> StorageResult<bool> {
+        Ok(tokio::fs::metadata(self.path(key)).await.is_ok())
+    }
+
+    async fn list(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(Box::new(e)),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                } else if let Some(key) = self.key(&path) {
+                    if key.starts_with(prefix) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<ObjectMeta> {
+        let metadata = match tokio::fs::metadata(self.path(key)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(not_found(key)),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: metadata.len(),
+            last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        })
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> StorageResult<()> {
+        //? Fast path for whole directories such as a user's `{uid}/` prefix
+        if prefix.ends_with('/') {
+            return match tokio::fs::remove_dir_all(self.path(prefix)).await {
+                Ok(_) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(Box::new(e)),
+            };
+        }
+
+        for key in self.list(prefix).await? {
+            self.delete(&key).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn put_stream(&self, key: &str, mut stream: ByteStream) -> StorageResult<u64> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut written = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let result = match chunk {
+                Ok(chunk) => file.write_all(&chunk).await.map(|_| chunk.len()),
+                Err(e) => Err(std::io::Error::other(e)),
+            };
+
+            match result {
+                Ok(len) => written += len as u64,
+                Err(e) => {
+                    drop(file);
+                    tokio::fs::remove_file(&path).await.ok();
+                    return Err(Box::new(e));
+                }
+            }
+        }
+
+        file.flush().await?;
+        Ok(written)
+    }
+
+    async fn get_stream(&self, key: &str) -> StorageResult<ByteStream> {
+        let file = match tokio::fs::File::open(self.path(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(not_found(key)),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        Ok(Box::pin(ReaderStream::new(file).map_err(|e| e.into())))
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<ByteStream> {
+        let mut file = match tokio::fs::File::open(self.path(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(not_found(key)),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        file.seek(SeekFrom::Start(start)).await?;
+        let reader = file.take(end - start + 1);
+
+        Ok(Box::pin(ReaderStream::new(reader).map_err(|e| e.into())))
+    }
+}
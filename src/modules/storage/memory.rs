@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, sync::RwLock};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+
+use super::{not_found, ObjectMeta, StorageBackend, StorageResult};
+
+/// Keeps every object in memory, useful for tests and throwaway instances.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: RwLock<BTreeMap<String, (Bytes, DateTime<Utc>)>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn put(&self, key: &str, bytes: Bytes) -> StorageResult<()> {
+        let mut objects = self.objects.write().unwrap();
+        objects.insert(key.to_string(), (bytes, Utc::now()));
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Bytes> {
+        let objects = self.objects.read().unwrap();
+        match objects.get(key) {
+            Some((bytes, _)) => Ok(bytes.clone()),
+            None => Err(not_found(key)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let mut objects = self.objects.write().unwrap();
+        match objects.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(not_found(key)),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> StorageResult<bool> {
+        Ok(self.objects.read().unwrap().contains_key(key))
+    }
+
+    async fn list(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let objects = self.objects.read().unwrap();
+        Ok(objects
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<ObjectMeta> {
+        let objects = self.objects.read().unwrap();
+        match objects.get(key) {
+            Some((bytes, modified)) => Ok(ObjectMeta {
+                key: key.to_string(),
+                size: bytes.len() as u64,
+                last_modified: Some(*modified),
+            }),
+            None => Err(not_found(key)),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> StorageResult<()> {
+        let mut objects = self.objects.write().unwrap();
+        objects.retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_memory_storage() {
+    let storage = MemoryStorage::new();
+
+    storage
+        .put("a/1.mgo", Bytes::from_static(b"one"))
+        .await
+        .unwrap();
+    storage
+        .put("a/2.mgo", Bytes::from_static(b"two"))
+        .await
+        .unwrap();
+    storage
+        .put("b/1.mgo", Bytes::from_static(b"three"))
+        .await
+        .unwrap();
+
+    assert_eq!(storage.get("a/2.mgo").await.unwrap(), "two");
+    assert_eq!(storage.stat("b/1.mgo").await.unwrap().size, 5);
+    assert_eq!(
+        storage.list("a/").await.unwrap(),
+        vec!["a/1.mgo", "a/2.mgo"]
+    );
+
+    storage.delete_prefix("a/").await.unwrap();
+
+    assert!(!storage.exists("a/1.mgo").await.unwrap());
+    assert!(storage.exists("b/1.mgo").await.unwrap());
+    assert!(storage.get("a/1.mgo").await.is_err());
+}
@@ -0,0 +1,144 @@
+//! Pluggable storage backends for encrypted `.mgo` blobs.
+//!
+//! Route handlers only ever talk to an [`Arc<dyn StorageBackend>`], so custom backends
+//! (in-memory, replicated, tiered, ...) can be dropped into [`crate::AppState`] without
+//! touching the routes.
+
+use std::{fmt::Debug, pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+
+use super::config::StorageConfig;
+
+pub mod local;
+pub mod memory;
+pub mod s3;
+
+pub use self::local::LocalStorage;
+pub use self::memory::MemoryStorage;
+pub use self::s3::S3Storage;
+
+pub type StorageError = Box<dyn std::error::Error + Send + Sync>;
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// A stream of object bytes, as read from or written to a backend.
+pub type ByteStream = Pin<Box<dyn Stream<Item = StorageResult<Bytes>> + Send>>;
+
+/// Metadata about a single stored object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+pub trait StorageBackend: Debug + Send + Sync {
+    /// A short, human readable name used in the startup logs.
+    fn name(&self) -> &'static str;
+
+    async fn put(&self, key: &str, bytes: Bytes) -> StorageResult<()>;
+    async fn get(&self, key: &str) -> StorageResult<Bytes>;
+    async fn delete(&self, key: &str) -> StorageResult<()>;
+    async fn exists(&self, key: &str) -> StorageResult<bool>;
+
+    /// Lists the keys of every object whose key starts with `prefix`.
+    async fn list(&self, prefix: &str) -> StorageResult<Vec<String>>;
+    async fn stat(&self, key: &str) -> StorageResult<ObjectMeta>;
+
+    /// Removes every object whose key starts with `prefix`, succeeding if there are none.
+    async fn delete_prefix(&self, prefix: &str) -> StorageResult<()>;
+
+    /// Writes an object from a stream, returning the number of bytes written.
+    ///
+    /// The default implementation buffers the whole stream, backends that can write
+    /// incrementally should override it. If the stream yields an error nothing is stored.
+    async fn put_stream(&self, key: &str, mut stream: ByteStream) -> StorageResult<u64> {
+        let mut bytes = BytesMut::new();
+
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk?);
+        }
+
+        let len = bytes.len() as u64;
+        self.put(key, bytes.freeze()).await?;
+        Ok(len)
+    }
+
+    /// Reads an object as a stream, the default implementation reads it all at once.
+    async fn get_stream(&self, key: &str) -> StorageResult<ByteStream> {
+        let bytes = self.get(key).await?;
+        Ok(Box::pin(stream::once(async move { Ok(bytes) })))
+    }
+
+    /// Reads the inclusive byte range `start..=end` of an object, stopping early at its end.
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<ByteStream> {
+        let stream = self.get_stream(key).await?;
+        Ok(slice_stream(stream, start, end - start + 1))
+    }
+}
+
+/// Skips the first `skip` bytes of a stream and ends it after `take` more bytes.
+pub fn slice_stream(stream: ByteStream, skip: u64, take: u64) -> ByteStream {
+    Box::pin(stream::try_unfold(
+        (stream, skip, take),
+        |(mut stream, mut skip, mut take)| async move {
+            while take > 0 {
+                let mut chunk = match stream.next().await {
+                    Some(chunk) => chunk?,
+                    None => break,
+                };
+
+                if skip >= chunk.len() as u64 {
+                    skip -= chunk.len() as u64;
+                    continue;
+                }
+
+                let chunk = chunk.split_off(skip as usize);
+                let chunk = chunk.slice(..(take.min(chunk.len() as u64) as usize));
+                take -= chunk.len() as u64;
+
+                return Ok(Some((chunk, (stream, 0, take))));
+            }
+
+            Ok(None)
+        },
+    ))
+}
+
+/// Builds the backend selected in `config.toml`.
+pub fn from_config(config: &StorageConfig) -> StorageResult<Arc<dyn StorageBackend>> {
+    if config.local.enabled {
+        Ok(Arc::new(LocalStorage::new(&config.local.path)))
+    } else {
+        Ok(Arc::new(S3Storage::new(&config.s3)?))
+    }
+}
+
+/// The key of a user's file, mirroring the `{uid}/{hash}.mgo` on-disk layout.
+pub fn object_key(uid: &str, hash: &str) -> String {
+    format!("{}/{}.mgo", uid, hash)
+}
+
+/// The prefix under which all of a user's files are stored.
+pub fn user_prefix(uid: &str) -> String {
+    format!("{}/", uid)
+}
+
+pub(crate) fn not_found(key: &str) -> StorageError {
+    Box::new(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("The object {} does not exist", key),
+    ))
+}
+
+/// Whether `error` is the [`not_found`] error of a missing object.
+pub fn is_not_found(error: &StorageError) -> bool {
+    matches!(
+        error.downcast_ref::<std::io::Error>(),
+        Some(e) if e.kind() == std::io::ErrorKind::NotFound
+    )
+}
@@ -0,0 +1,297 @@
+use ::s3::{bucket::Bucket, creds::Credentials, region::Region};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, TryStreamExt};
+use std::io::Error;
+use tokio_util::io::StreamReader;
+
+use super::{not_found, ByteStream, ObjectMeta, StorageBackend, StorageResult};
+use crate::modules::config::S3StorageConfig;
+
+/// Size of the ranged requests used to stream objects out of the bucket.
+const READ_SIZE: u64 = 1024 * 1024;
+
+/// Stores objects in an S3 compatible bucket, using the object key as-is.
+#[derive(Clone, Debug)]
+pub struct S3Storage {
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3StorageConfig) -> StorageResult<S3Storage> {
+        let region = Region::Custom {
+            region: config.region.clone(),
+            endpoint: config.endpoint.clone(),
+        };
+
+        let credentials = Credentials::new(
+            Some(&config.access_key),
+            Some(&config.secret_key),
+            None,
+            None,
+            None,
+        )?;
+
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+
+        if config.path_style {
+            bucket.set_path_style();
+        }
+
+        Ok(S3Storage { bucket })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    fn name(&self) -> &'static str {
+        "S3"
+    }
+
+    async fn put(&self, key: &str, bytes: Bytes) -> StorageResult<()> {
+        let (_, status) = self.bucket.put_object(key, &bytes).await?;
+        check_status(key, status)
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Bytes> {
+        let (data, status) = self.bucket.get_object(key).await?;
+        check_status(key, status)?;
+        Ok(Bytes::from(data))
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let (_, status) = self.bucket.delete_object(key).await?;
+        check_status(key, status)
+    }
+
+    async fn exists(&self, key: &str) -> StorageResult<bool> {
+        let (_, status) = self.bucket.head_object(key).await?;
+
+        match status {
+            404 => Ok(false),
+            _ => check_status(key, status).map(|_| true),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let results = self.bucket.list(prefix.to_string(), None).await?;
+
+        Ok(results
+            .into_iter()
+            .flat_map(|result| result.contents)
+            .map(|object| object.key)
+            .collect())
+    }
+
+    async fn stat(&self, key: &str) -> StorageResult<ObjectMeta> {
+        let (head, status) = self.bucket.head_object(key).await?;
+        check_status(key, status)?;
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: head.content_length.unwrap_or_default() as u64,
+            last_modified: head
+                .last_modified
+                .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+                .map(|date| date.with_timezone(&Utc)),
+        })
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> StorageResult<()> {
+        for key in self.list(prefix).await? {
+            self.delete(&key).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn put_stream(&self, key: &str, stream: ByteStream) -> StorageResult<u64> {
+        let mut reader = CountingReader {
+            reader: StreamReader::new(stream.map_err(Error::other)),
+            read: 0,
+        };
+
+        let status = self.bucket.put_object_stream(&mut reader, key).await?;
+        check_status(key, status)?;
+
+        Ok(reader.read)
+    }
+
+    async fn get_stream(&self, key: &str) -> StorageResult<ByteStream> {
+        let size = self.stat(key).await?.size;
+        Ok(self.read_range(key, 0, size))
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> StorageResult<ByteStream> {
+        let size = self.stat(key).await?.size;
+        Ok(self.read_range(key, start, (end + 1).min(size)))
+    }
+}
+
+impl S3Storage {
+    /// Reads `start..end` with consecutive ranged requests so the object is never held in memory.
+    fn read_range(&self, key: &str, start: u64, end: u64) -> ByteStream {
+        let bucket = self.bucket.clone();
+        let key = key.to_string();
+
+        let stream = stream::try_unfold(start, move |start| {
+            let bucket = bucket.clone();
+            let key = key.clone();
+
+            async move {
+                if start >= end {
+                    return Ok(None);
+                }
+
+                let last = (start + READ_SIZE).min(end) - 1;
+                let (data, status) = bucket.get_object_range(&key, start, Some(last)).await?;
+                check_status(&key, status)?;
+
+                Ok(Some((Bytes::from(data), last + 1)))
+            }
+        });
+
+        Box::pin(stream)
+    }
+}
+
+/// Counts the bytes handed to `put_object_stream`, which only reports a status code.
+struct CountingReader<R> {
+    reader: R,
+    read: u64,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = std::pin::Pin::new(&mut self.reader).poll_read(cx, buf);
+        self.read += (buf.filled().len() - before) as u64;
+        poll
+    }
+}
+
+fn check_status(key: &str, status: u16) -> StorageResult<()> {
+    match status {
+        200..=299 => Ok(()),
+        404 => Err(not_found(key)),
+        _ => Err(Box::new(Error::other(format!(
+            "S3 request for {} failed with status code {}",
+            key, status
+        )))),
+    }
+}
+
+#[actix_web::test]
+async fn test_s3_storage() {
+    use actix_web::{http::Method, web, App, HttpRequest, HttpResponse, HttpServer};
+    use futures_util::StreamExt;
+    use std::{collections::HashMap, sync::Mutex};
+
+    type Objects = web::Data<Mutex<HashMap<String, Bytes>>>;
+
+    //? A path-style endpoint that keeps objects in memory and does not check signatures
+    async fn endpoint(request: HttpRequest, body: Bytes, objects: Objects) -> HttpResponse {
+        let key = request.path().to_string();
+        let mut objects = objects.lock().unwrap();
+
+        if request.method() == Method::PUT {
+            objects.insert(key, body);
+            return HttpResponse::Ok().insert_header(("ETag", "\"0\"")).finish();
+        }
+
+        if request.method() == Method::DELETE {
+            objects.remove(&key);
+            return HttpResponse::NoContent().finish();
+        }
+
+        let data = match objects.get(&key) {
+            Some(data) => data.clone(),
+            None => return HttpResponse::NotFound().finish(),
+        };
+
+        let range = request
+            .headers()
+            .get("Range")
+            .and_then(|range| range.to_str().ok()?.strip_prefix("bytes="))
+            .and_then(|range| range.split_once('-'))
+            .map(|(start, end)| {
+                (
+                    start.parse::<usize>().unwrap(),
+                    end.parse::<usize>().unwrap(),
+                )
+            });
+
+        match range {
+            Some((start, end)) => HttpResponse::PartialContent().body(data.slice(start..=end)),
+            None => HttpResponse::Ok()
+                .insert_header(("Last-Modified", "Tue, 15 Nov 1994 08:12:31 GMT"))
+                .body(data),
+        }
+    }
+
+    let objects: Objects = web::Data::new(Mutex::new(HashMap::new()));
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(objects.clone())
+            .app_data(web::PayloadConfig::new(usize::MAX))
+            .default_service(web::to(endpoint))
+    })
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let address = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+
+    let storage = S3Storage::new(&S3StorageConfig {
+        enabled: true,
+        bucket: "mgo".to_string(),
+        endpoint: format!("http://{}", address),
+        region: "local".to_string(),
+        access_key: "access".to_string(),
+        secret_key: "secret".to_string(),
+        path_style: true,
+    })
+    .unwrap();
+
+    //? Large enough to be read back with several ranged requests
+    let large = Bytes::from((0..READ_SIZE * 2 + 5).map(|i| i as u8).collect::<Vec<_>>());
+
+    storage
+        .put("a/1.mgo", Bytes::from_static(b"Hello World"))
+        .await
+        .unwrap();
+    let written = storage
+        .put_stream(
+            "a/2.mgo",
+            Box::pin(stream::iter(vec![
+                Ok(large.slice(..10)),
+                Ok(large.slice(10..)),
+            ])),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(written, large.len() as u64);
+    assert_eq!(storage.get("a/1.mgo").await.unwrap(), "Hello World");
+    assert_eq!(storage.stat("a/1.mgo").await.unwrap().size, 11);
+    assert!(storage.exists("a/1.mgo").await.unwrap());
+
+    let range = storage.get_range("a/1.mgo", 6, 100).await.unwrap();
+    let range: Vec<_> = range.map(Result::unwrap).collect().await;
+    assert_eq!(range.concat(), b"World");
+
+    let whole = storage.get_stream("a/2.mgo").await.unwrap();
+    let whole: Vec<_> = whole.map(Result::unwrap).collect().await;
+    assert_eq!(whole.len(), 3);
+    assert_eq!(whole.concat(), large);
+
+    storage.delete("a/1.mgo").await.unwrap();
+
+    assert!(!storage.exists("a/1.mgo").await.unwrap());
+    assert!(storage.get("a/1.mgo").await.is_err());
+    assert!(storage.stat("a/1.mgo").await.is_err());
+}
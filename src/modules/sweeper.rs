@@ -0,0 +1,99 @@
+//! Background removal of expired files and tokens.
+//!
+//! Expired files are purged rather than deleted: their blob is deleted and the quota refunded,
+//! but the record stays behind for a while so links to it can answer with 410 Gone.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use log::{error, info};
+
+use super::{
+    config::FilesConfig,
+    database::DatabaseBackend,
+    storage::{is_not_found, StorageBackend, StorageError},
+};
+use crate::structs::files::File;
+
+/// Deletes the blob of `file`, marks it as purged and refunds its size to the uploader.
+///
+/// Returns `false` without refunding anything if another request already purged the file.
+pub async fn purge(
+    database: &dyn DatabaseBackend,
+    storage: &dyn StorageBackend,
+    file: &File,
+) -> Result<bool, StorageError> {
+    //? The blob goes first so a failed delete leaves the file to be retried by the next sweep,
+    //? a blob that is already gone was removed by an earlier attempt or a concurrent purge
+    match storage.delete(&file.storage_key()).await {
+        Ok(_) => {}
+        Err(e) if is_not_found(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    //? Claiming the record keeps a concurrent sweep or delete from refunding the file twice
+    if !database.mark_purged(file._id).await? {
+        return Ok(false);
+    }
+
+    database.add_used_quota(file.uploader, -file.size).await?;
+
+    Ok(true)
+}
+
+/// Purges every expired file and forgets the ones that expired more than `retention` seconds ago,
+/// expired tokens are deleted right away.
+pub async fn sweep(
+    database: &dyn DatabaseBackend,
+    storage: &dyn StorageBackend,
+    retention: i64,
+) -> Result<usize, StorageError> {
+    let now = Utc::now();
+    let mut purged = 0;
+
+    //? One broken file must not keep the rest from being purged
+    for file in database.expired_files(now).await? {
+        match purge(database, storage, &file).await {
+            Ok(true) => purged += 1,
+            Ok(false) => {}
+            Err(e) => error!("Failed to purge file {}: {}", file.public_id(), e),
+        }
+    }
+
+    let forgotten = bson::DateTime::from_millis(
+        now.timestamp_millis()
+            .saturating_sub(retention.saturating_mul(1000)),
+    );
+
+    database.forget_purged(forgotten.to_chrono()).await?;
+    database.forget_expired_tokens(now).await?;
+
+    Ok(purged)
+}
+
+/// Runs [`sweep`] every `sweep_interval` seconds for as long as the server is up.
+pub fn spawn(
+    database: Arc<dyn DatabaseBackend>,
+    storage: Arc<dyn StorageBackend>,
+    config: FilesConfig,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.sweep_interval));
+
+        loop {
+            interval.tick().await;
+
+            match sweep(
+                database.as_ref(),
+                storage.as_ref(),
+                config.expired_retention,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(purged) => info!("Removed {} expired file(s)", purged),
+                Err(e) => error!("Failed to remove expired files: {}", e),
+            }
+        }
+    });
+}
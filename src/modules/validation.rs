@@ -0,0 +1,155 @@
+//! Validation of user supplied account details.
+
+use serde::Serialize;
+
+use super::config::PasswordConfig;
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+pub const EMAIL_MAX_LENGTH: usize = 254;
+
+/// A field that failed validation, reported to the client as is.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new<T: Into<String>>(field: &'static str, message: T) -> FieldError {
+        FieldError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Usernames are 3 to 32 ASCII letters, digits, `_`, `-` or `.`, starting with a letter or digit.
+pub fn validate_username(username: &str) -> Result<(), FieldError> {
+    let length = username.chars().count();
+
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(FieldError::new(
+            "username",
+            format!(
+                "must be between {} and {} characters long",
+                USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH
+            ),
+        ));
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(FieldError::new(
+            "username",
+            "may only contain letters, digits, '_', '-' and '.'",
+        ));
+    }
+
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(FieldError::new(
+            "username",
+            "must start with a letter or a digit",
+        ));
+    }
+
+    Ok(())
+}
+
+/// A deliberately loose syntax check, whether the address exists can only be told by mailing it.
+pub fn validate_email(email: &str) -> Result<(), FieldError> {
+    if email.len() > EMAIL_MAX_LENGTH {
+        return Err(FieldError::new(
+            "email",
+            format!("must be at most {} characters long", EMAIL_MAX_LENGTH),
+        ));
+    }
+
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    };
+
+    if !valid {
+        return Err(FieldError::new("email", "is not a valid email address"));
+    }
+
+    Ok(())
+}
+
+pub fn validate_password(
+    password: &str,
+    username: &str,
+    config: &PasswordConfig,
+) -> Result<(), FieldError> {
+    let length = password.chars().count();
+
+    if length < config.min_length {
+        return Err(FieldError::new(
+            "password",
+            format!("must be at least {} characters long", config.min_length),
+        ));
+    }
+
+    if length > config.max_length {
+        return Err(FieldError::new(
+            "password",
+            format!("must be at most {} characters long", config.max_length),
+        ));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|present| **present)
+    .count();
+
+    if classes < config.min_classes {
+        return Err(FieldError::new(
+            "password",
+            format!(
+                "must contain at least {} of lowercase letters, uppercase letters, digits and symbols",
+                config.min_classes
+            ),
+        ));
+    }
+
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        return Err(FieldError::new("password", "must not contain the username"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_validation() {
+    let config = PasswordConfig::default();
+
+    assert!(validate_username("magnesium_42").is_ok());
+    assert!(validate_username("mg").is_err());
+    assert!(validate_username("_magnesium").is_err());
+    assert!(validate_username("magnesium oxide").is_err());
+
+    assert!(validate_email("user@example.com").is_ok());
+    assert!(validate_email("user@localhost").is_err());
+    assert!(validate_email("user@@example.com").is_err());
+    assert!(validate_email("us er@example.com").is_err());
+
+    assert!(validate_password("correct horse battery", "magnesium", &config).is_ok());
+    assert!(validate_password("short", "magnesium", &config).is_err());
+    assert!(validate_password("aaaaaaaaaaaa", "magnesium", &config).is_err());
+    assert!(validate_password("Magnesium1234", "magnesium", &config).is_err());
+}
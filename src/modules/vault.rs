@@ -0,0 +1,106 @@
+//! Per-user key vaults.
+//!
+//! A user with a vault has an X25519 key pair. Every upload seals its link key and nonce to the
+//! public key, so the owner can recover working links later. The private key is either held by
+//! the user alone, or stored wrapped with a key derived from their password; the server never
+//! keeps a plaintext key at rest.
+
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit},
+    Aes256GcmSiv, Key, Nonce,
+};
+use rand::rngs::OsRng;
+use sha3::{Digest, Sha3_256};
+use std::io::{Error, ErrorKind};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// Size of an X25519 public or private key.
+pub const KEY_SIZE: usize = 32;
+
+/// Creates a new key pair, returning the private and the public key.
+pub fn generate() -> ([u8; KEY_SIZE], [u8; KEY_SIZE]) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+
+    (secret.to_bytes(), public.to_bytes())
+}
+
+/// Derives the key a single sealed box is encrypted with from the shared secret and both
+/// public keys.
+fn box_key(shared: &[u8], ephemeral: &[u8], recipient: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"magnesium-oxide vault");
+    hasher.update(shared);
+    hasher.update(ephemeral);
+    hasher.update(recipient);
+
+    hasher.finalize().into()
+}
+
+/// Seals `data` so that only the holder of the private key for `public_key` can open it.
+///
+/// The result is the ephemeral public key followed by the ciphertext, base64 encoded.
+pub fn seal(public_key: &[u8], data: &[u8]) -> std::io::Result<String> {
+    let recipient: [u8; KEY_SIZE] = public_key
+        .try_into()
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid public key length"))?;
+    let recipient = PublicKey::from(recipient);
+
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient);
+
+    //? Every box has a key of its own, so a fixed nonce is never reused under the same key
+    let key = box_key(
+        shared.as_bytes(),
+        ephemeral_public.as_bytes(),
+        recipient.as_bytes(),
+    );
+    let sealed = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&key))
+        .encrypt(Nonce::from_slice(&[0u8; 12]), data)
+        .map_err(|_| Error::other("Failed to encrypt data"))?;
+
+    Ok(base64::encode(
+        [ephemeral_public.as_bytes(), sealed.as_slice()].concat(),
+    ))
+}
+
+/// Opens a box created by [`seal`], `None` if it was not sealed to `private_key` or was altered.
+pub fn open(private_key: &[u8], sealed: &str) -> Option<Vec<u8>> {
+    let secret: [u8; KEY_SIZE] = private_key.try_into().ok()?;
+    let secret = StaticSecret::from(secret);
+
+    let sealed = base64::decode(sealed).ok()?;
+
+    if sealed.len() < KEY_SIZE {
+        return None;
+    }
+
+    let (ephemeral, sealed) = sealed.split_at(KEY_SIZE);
+    let ephemeral: [u8; KEY_SIZE] = ephemeral.try_into().ok()?;
+    let ephemeral = PublicKey::from(ephemeral);
+
+    let shared = secret.diffie_hellman(&ephemeral);
+    let key = box_key(
+        shared.as_bytes(),
+        ephemeral.as_bytes(),
+        PublicKey::from(&secret).as_bytes(),
+    );
+
+    Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&key))
+        .decrypt(Nonce::from_slice(&[0u8; 12]), sealed)
+        .ok()
+}
+
+#[test]
+fn test_vault() {
+    let (private_key, public_key) = generate();
+    let sealed = seal(&public_key, b"key and nonce").unwrap();
+
+    assert_eq!(
+        open(&private_key, &sealed).as_deref(),
+        Some(&b"key and nonce"[..])
+    );
+    assert_eq!(open(&generate().0, &sealed), None);
+    assert!(seal(&public_key[1..], b"key and nonce").is_err());
+}
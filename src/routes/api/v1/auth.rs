@@ -0,0 +1,186 @@
+use actix_web::{web::Form, HttpRequest, HttpResponse, Result};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+use crate::{
+    modules::{
+        database::{DatabaseBackend, DatabaseResult},
+        hashing::hash_string,
+        passwords::{authenticate, authenticate_unknown},
+    },
+    routes::{error::ApiError, state},
+    structs::{
+        roles::Role,
+        tokens::ApiToken,
+        users::{LoginRequest, User},
+        Permissions, Scopes,
+    },
+};
+
+/// Seconds a token issued by [`login`] stays valid, the sweeper deletes it afterwards.
+pub const LOGIN_LIFETIME: i64 = 60 * 60 * 24 * 30;
+
+/// The user behind a request and what the presented token allows them to do.
+pub struct Requester {
+    pub user: User,
+    pub scopes: Scopes,
+    pub role: Option<Role>,
+    pub permissions: Permissions,          //? Granted by the user's role
+    pub token: Option<ObjectId>,           //? `None` when the user's primary token was used
+    pub expires_at: Option<DateTime<Utc>>, //? When the token in use expires
+}
+
+impl Requester {
+    /// Whether the token carries `scopes` and the user's role grants `permissions`.
+    pub fn allows(&self, scopes: Scopes, permissions: Permissions) -> bool {
+        self.scopes.contains(scopes) && self.permissions.contains(permissions)
+    }
+}
+
+/// Looks up the user owning the token in the `Authorization` header.
+///
+/// Named tokens are checked first, the primary token stored on the user has every scope.
+/// Expired tokens and suspended users are treated as unknown.
+pub async fn requester(
+    request: &HttpRequest,
+    database: &dyn DatabaseBackend,
+) -> DatabaseResult<Option<Requester>> {
+    let token = match request
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(token) => hash_string(token),
+        None => return Ok(None),
+    };
+
+    let (user, scopes, token, expires_at) = match database.find_token(&token).await? {
+        Some(api_token) => {
+            if api_token.is_expired() {
+                return Ok(None);
+            }
+
+            database.touch_token(api_token._id).await?;
+
+            let user = database.find_user(api_token.user).await?;
+            let expires_at = api_token.expires_at.map(|date| date.to_chrono());
+            (user, api_token.scopes, Some(api_token._id), expires_at)
+        }
+        None => {
+            let user = database.find_user_by_token(&token).await?;
+            (user, Scopes::all(), None, None)
+        }
+    };
+
+    let user = match user {
+        Some(user) if !user.suspended => user,
+        _ => return Ok(None),
+    };
+
+    let role = database.find_role(&user.role).await?;
+    let permissions = match role {
+        Some(ref role) => role.permissions,
+        None => Permissions::empty(),
+    };
+
+    Ok(Some(Requester {
+        user,
+        scopes,
+        role,
+        permissions,
+        token,
+        expires_at,
+    }))
+}
+
+/// Resolves the requester and checks that they are allowed to do what `scopes` and
+/// `permissions` describe, this is the only place requests are authorized.
+pub async fn authorize(
+    request: &HttpRequest,
+    scopes: Scopes,
+    permissions: Permissions,
+) -> Result<Requester, ApiError> {
+    let state = state(request)?;
+
+    match requester(request, state.database.as_ref()).await? {
+        Some(requester) if !requester.scopes.contains(scopes) => Err(ApiError::Forbidden(
+            "The token is missing a required scope".to_string(),
+        )),
+        Some(requester) if !requester.permissions.contains(permissions) => {
+            Err(ApiError::forbidden())
+        }
+        Some(requester) => Ok(requester),
+        None => Err(ApiError::unauthorized()),
+    }
+}
+
+/// Replaces the secret of the token used by `requester`, invalidating the old one.
+async fn rotate(database: &dyn DatabaseBackend, requester: &Requester) -> DatabaseResult<String> {
+    let token = User::generate_token();
+
+    match requester.token {
+        Some(_id) => database.set_token_hash(_id, &hash_string(&token)).await?,
+        None => {
+            database
+                .set_user_token(requester.user._id, &hash_string(&token))
+                .await?
+        }
+    }
+
+    Ok(token)
+}
+
+pub async fn login(
+    request: HttpRequest,
+    data: Form<LoginRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+    let invalid = || ApiError::Unauthorized("Invalid username or password".to_string());
+
+    let user = match state.database.find_user_by_login(&data.username).await? {
+        Some(user) => user,
+        None => {
+            authenticate_unknown(&state.config.passwords, &data.password).await?;
+            return Err(invalid());
+        }
+    };
+
+    if !authenticate(
+        state.database.as_ref(),
+        &state.config.passwords,
+        &user,
+        &data.password,
+    )
+    .await?
+    {
+        return Err(invalid());
+    }
+
+    if user.suspended {
+        return Err(ApiError::Forbidden("This account is suspended".to_string()));
+    }
+
+    //? Every login gets its own token so sessions can be revoked independently, they expire so
+    //? that repeated logins do not pile up
+    let name = data.name.clone().unwrap_or_else(|| "login".to_string());
+    let expires_at = Utc::now() + Duration::seconds(LOGIN_LIFETIME);
+    let (api_token, token) = ApiToken::new(user._id, name, Scopes::all(), Some(expires_at));
+
+    state.database.insert_token(&api_token).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "id": api_token._id.to_hex(),
+        "token": token,
+        "expires_at": expires_at.to_rfc3339(),
+    })))
+}
+
+pub async fn rotate_token(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+    let requester = authorize(&request, Scopes::empty(), Permissions::empty()).await?;
+
+    let token = rotate(state.database.as_ref(), &requester).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "token": token })))
+}
@@ -1,189 +1,443 @@
 use actix_multipart::Multipart;
 use actix_web::{
-    web::{Path, Query},
-    Error, HttpRequest, HttpResponse, Result,
+    http::{
+        header::{
+            CacheControl, CacheDirective, ContentRange, ContentRangeSpec, ETag, EntityTag,
+            Header as _, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range,
+            ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, IF_RANGE, VARY,
+        },
+        Method, StatusCode,
+    },
+    web::{self, Form, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Result,
 };
 use base64::URL_SAFE_NO_PAD;
-use bson::{doc, oid::ObjectId};
-use bytes::BytesMut;
-use chrono::Utc;
-use futures_util::{StreamExt, TryStreamExt};
+use bson::oid::ObjectId;
+use bytes::Bytes;
+use chrono::{Duration, Utc};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use log::error;
 use serde_json::json;
-
+use std::time::{SystemTime, UNIX_EPOCH};
+use tera::Context;
+use tokio::sync::mpsc::{self, Sender};
 use uuid::Uuid;
 
 use crate::{
     modules::{
-        crypto::{decrypt_bytes, encrypt_bytes, generate_key, EncryptionKey},
-        hashing::{hash_bytes, hash_string},
+        compression::{self, decompress_stream, Compressor},
+        config::CompressionConfig,
+        container::{self, Compression, Header},
+        crypto::{generate_key, open_text, seal_text, EncryptionKey, Encryptor, CHUNK_SIZE},
+        hashing::hash_string,
+        ids::generate_id,
+        passphrases::{self, PASSPHRASE_HEADER},
+        slugs,
+        storage::{object_key, ByteStream, StorageError, StorageResult},
+        sweeper, vault,
     },
-    structs::users::User,
+    routes::{api::v1::auth::authorize, error::ApiError, state},
     structs::{
-        files::{File, FileDeleteRequest, FileGetRequest},
-        Privileges,
+        files::{File, FileDeleteRequest, FileGetRequest, FileUnlockRequest, FileUploadRequest},
+        Permissions, Scopes,
     },
     AppState,
 };
 
-pub async fn upload_file(request: HttpRequest, mut data: Multipart) -> Result<HttpResponse> {
-    let state = request.app_data::<AppState>().unwrap();
+pub async fn upload_file(
+    request: HttpRequest,
+    options: Query<FileUploadRequest>,
+    mut data: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
 
-    let files = state.database.collection::<File>("files");
-    let users = state.database.collection::<User>("users");
+    let requester = authorize(&request, Scopes::UPLOAD, Permissions::UPLOAD).await?;
 
-    let auth_header = request.headers().get("Authorization");
+    let lifetime = file_lifetime(
+        &options,
+        requester
+            .role
+            .as_ref()
+            .and_then(|role| role.max_file_lifetime),
+        state.config.files.default_lifetime,
+    )?;
 
-    if auth_header.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    if matches!(options.max_downloads, Some(max_downloads) if max_downloads < 1) {
+        return Err(ApiError::bad_request(
+            "The download limit must be at least 1",
+        ));
     }
 
-    let token_hash = hash_string(auth_header.unwrap().to_str().unwrap());
-
-    let uploader = users
-        .find_one(
-            doc! {
-                "token": token_hash
-            },
-            None,
-        )
-        .await
-        .unwrap();
+    let passphrase = passphrase(&request);
 
-    if uploader.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    if matches!(passphrase, Some(ref passphrase) if passphrase.is_empty() || passphrase.len() > state.config.passwords.max_length)
+    {
+        return Err(ApiError::bad_request(format!(
+            "The passphrase must be between 1 and {} bytes long",
+            state.config.passwords.max_length
+        )));
     }
 
-    let uploader = uploader.unwrap();
+    let uploader = requester.user;
 
-    if !uploader.privileges.contains(Privileges::USER) {
-        return Ok(
-            HttpResponse::Unauthorized().body("Your privileges are not sufficient to upload files")
-        );
-    }
+    let file_id = ObjectId::new();
+    let key = object_key(&uploader._id.to_hex(), &file_id.to_hex());
+    let crypto = generate_key();
 
-    let storage = state.storage.clone();
-    let mut file_name = String::new();
-    let mut file_mimetype = String::new();
-    let mut file_bits = vec![];
+    //? The link of a protected file carries a key of its own, the file key is only stored wrapped
+    let (link_key, lock) = match passphrase {
+        Some(passphrase) => {
+            let link_key = generate_key().key;
+            let (secret, key, config) = (
+                link_key.clone(),
+                crypto.key.clone(),
+                state.config.passwords.clone(),
+            );
 
-    while let Some(mut field) = data.try_next().await.unwrap() {
-        while let Some(chunk) = field.next().await {
-            file_bits.extend_from_slice(&chunk?);
-        }
+            let lock = web::block(move || passphrases::lock(&passphrase, &secret, &key, &config))
+                .await?
+                .map_err(|e| ApiError::internal(format!("passphrase lock: {}", e)))?;
 
-        if field.name() != "file" {
-            return Ok(HttpResponse::BadRequest().body("Invalid file"));
+            (link_key, Some(lock))
         }
+        None => (crypto.key.clone(), None),
+    };
 
-        file_name = field
-            .content_disposition()
-            .get_filename()
-            .unwrap()
-            .to_string();
-        file_mimetype = field.content_type().to_string();
-    }
+    //? The upload is encrypted and handed to the storage backend while it is still arriving,
+    //? the bounded channel keeps only a few chunks in memory at any time
+    let (sender, receiver) = mpsc::channel::<StorageResult<Bytes>>(4);
+    let chunks: ByteStream = Box::pin(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    }));
 
-    let file_hash = hash_bytes(&file_bits);
-    let file_size = file_bits.len() as i64;
+    //? The in-browser viewer behind fragment links can not decompress, so those are stored as-is
+    let uncompressed = CompressionConfig::default();
+    let compression = match options.fragment {
+        true => &uncompressed,
+        false => &state.config.compression,
+    };
 
-    let crypto = generate_key();
-    let file_bits = match encrypt_bytes(&crypto, &BytesMut::from(file_bits.as_slice())) {
-        Ok(bytes) => bytes,
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().body("Failed to encrypt file"));
+    let (stored, received) = futures_util::join!(
+        state.storage.put_stream(&key, chunks),
+        receive_file(
+            &mut data,
+            &crypto,
+            compression,
+            sender,
+            uploader.quota.available - uploader.quota.used
+        )
+    );
+
+    let received = match received {
+        Ok(received) => received,
+        Err(e) => {
+            state.storage.delete(&key).await.ok();
+            return Err(e);
         }
     };
 
-    if uploader.quota.used + file_size >= uploader.quota.available {
-        return Ok(HttpResponse::BadRequest()
-            .body("The file you are trying to upload would exceed your available quota"));
-    }
+    stored?;
+
+    let id = match allocate_id(state).await {
+        Ok(id) => id,
+        Err(e) => {
+            state.storage.delete(&key).await.ok();
+            return Err(e);
+        }
+    };
+
+    let slug = options
+        .slug
+        .or(uploader.slug)
+        .unwrap_or(state.config.files.slug);
+    let slug = slugs::encode(slug, &id).unwrap_or_else(|| id.clone());
 
+    let file_name = received.name;
+    let file_size = received.size;
     let dkey = Uuid::new_v4().to_string();
 
+    let sealed = state.config.files.encrypt_metadata;
+
+    let (filename, mimetype) = match sealed {
+        true => (
+            seal_text(&crypto.key, &file_name)?,
+            seal_text(&crypto.key, &received.mimetype)?,
+        ),
+        false => (file_name.clone(), received.mimetype),
+    };
+
+    //? Lets the uploader recover the link later, only their vault's private key can open it
+    let vault_key = match uploader.vault {
+        Some(ref vault) => {
+            let public_key = base64::decode(&vault.public_key)
+                .map_err(|e| ApiError::internal(format!("vault public key: {}", e)))?;
+
+            Some(vault::seal(
+                &public_key,
+                &[link_key.as_slice(), crypto.nonce.as_slice()].concat(),
+            )?)
+        }
+        None => None,
+    };
+
     let file = File {
-        _id: ObjectId::new(),
-        filename: file_name.clone(),
-        mimetype: file_mimetype,
+        _id: file_id,
+        id: Some(id.clone()),
+        filename,
+        mimetype,
         uploader: uploader._id,
-        hash: file_hash.clone(),
+        hash: String::new(), //? Only legacy files are addressed by their content hash
         dkey: hash_string(&dkey),
         size: file_size,
+        blob: Some(key.clone()),
         created_at: Utc::now(),
+        expires_at: lifetime
+            .map(|lifetime| bson::DateTime::from_chrono(Utc::now() + Duration::seconds(lifetime))),
+        purged: false,
+        max_downloads: options.max_downloads,
+        downloads: 0,
+        lock,
+        sealed,
+        vault_key,
+        compression: received.compression,
     };
 
-    storage
-        .put_file(file.uploader.to_hex().as_str(), &file_hash, &file_bits)
-        .await?;
-
-    let key_str = base64::encode_config(crypto.key, URL_SAFE_NO_PAD);
+    let key_str = base64::encode_config(link_key, URL_SAFE_NO_PAD);
     let nonce_str = base64::encode_config(crypto.nonce, URL_SAFE_NO_PAD);
 
-    let file_check = files
-        .find_one(doc! {"hash": &file_hash}, None)
-        .await
-        .unwrap();
-
-    if file_check.is_none() {
-        files.insert_one(&file, None).await.unwrap();
-
-        users
-        .update_one(
-            doc! {"_id": uploader._id},
-            doc! {"$set": {"quota.used": uploader.quota.used + file_size, "updated_at": file.created_at}},
-            None,
-        ).await.unwrap();
-    } else {
-        files
-            .delete_one(doc! {"_id": file_check.unwrap()._id}, None)
-            .await
-            .unwrap();
-        files.insert_one(file, None).await.unwrap();
+    //? Every file is encrypted with its own random key, so two uploads of identical content never
+    //? produce the same blob and can not share one. Each upload is its own record with its own
+    //? blob instead, which also leaves files of other users alone.
+    if let Err(e) = state.database.insert_file(&file).await {
+        state.storage.delete(&key).await.ok();
+        return Err(e.into());
     }
 
-    return Ok(HttpResponse::Created().json(json!({
-        "hash": file_hash,
-        "ext": file_name.split('.').last().unwrap(),
+    state
+        .database
+        .add_used_quota(uploader._id, file_size)
+        .await?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "id": id,
+        "hash": id, //? Deprecated, kept for one release for clients that link by `hash`
+        "slug": slug,
+        "ext": file_name.rsplit('.').next().unwrap_or_default(),
         "key": key_str,
         "nonce": nonce_str,
-        "dkey": dkey
-    })));
+        "dkey": dkey,
+        "expires_at": file.expires_at.map(|date| date.to_chrono().to_rfc3339()),
+        "max_downloads": file.max_downloads,
+        "protected": file.lock.is_some(),
+    })))
 }
 
-pub async fn delete_file(
-    request: HttpRequest,
-    data: Query<FileDeleteRequest>,
-) -> Result<HttpResponse> {
-    let state = request.app_data::<AppState>().unwrap();
-    let files = state.database.collection::<File>("files");
-    let storage = state.storage.clone();
+/// Longest lifetime that can be requested for a file, in seconds.
+pub const MAX_LIFETIME: i64 = 60 * 60 * 24 * 365 * 100;
 
-    let dkey = hash_string(&data.dkey);
+/// Works out how many seconds an upload may live, `None` if it never expires.
+///
+/// An explicitly requested lifetime above the role's maximum is refused, while the server
+/// default is silently shortened to it.
+fn file_lifetime(
+    options: &FileUploadRequest,
+    max: Option<i64>,
+    default: Option<i64>,
+) -> Result<Option<i64>, ApiError> {
+    let requested = match (options.expires_in, options.expires_at) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::bad_request(
+                "Only one of expires_in and expires_at may be given",
+            ));
+        }
+        (Some(seconds), None) => Some(seconds),
+        (None, Some(date)) => Some((date - Utc::now()).num_seconds()),
+        (None, None) => None,
+    };
 
-    let file = files
-        .find_one(doc! {"hash": &data.hash}, None)
-        .await
-        .unwrap();
+    //? Roles and the config are validated too, but a lifetime must never overflow a date
+    let limit = max.unwrap_or(MAX_LIFETIME).min(MAX_LIFETIME);
 
-    if file.is_none() {
-        return Ok(HttpResponse::NotFound()
-            .body("The specified file does not exist or your deletion key is invalid"));
+    match requested {
+        Some(seconds) if seconds <= 0 => {
+            Err(ApiError::bad_request("The expiry must be in the future"))
+        }
+        Some(seconds) if seconds > limit => Err(ApiError::bad_request(format!(
+            "Files can live for at most {} seconds",
+            limit
+        ))),
+        Some(seconds) => Ok(Some(seconds)),
+        None => Ok(default.or(max).map(|seconds| seconds.min(limit))),
     }
+}
 
-    let file = file.unwrap();
+/// Picks a public ID that is not in use yet.
+async fn allocate_id(state: &AppState) -> Result<String, ApiError> {
+    //? Collisions are unlikely with sane settings, but small alphabets and lengths are allowed
+    for _ in 0..10 {
+        let candidate = generate_id(
+            state.config.files.id_length,
+            &state.config.files.id_alphabet,
+        );
 
-    if dkey != file.dkey {
-        return Ok(HttpResponse::Unauthorized().body("Invalid deletion key"));
+        if !state.database.public_id_exists(&candidate).await? {
+            return Ok(candidate);
+        }
     }
 
-    storage
-        .remove_file(file.uploader.to_hex().as_str(), &file.hash)
-        .await?;
+    Err(ApiError::internal(
+        "no unused file ID found after 10 attempts",
+    ))
+}
+
+struct ReceivedFile {
+    name: String,
+    mimetype: String,
+    size: i64,
+    compression: Compression,
+}
+
+/// Hashes, compresses and encrypts the `file` field of an upload, sending the container to
+/// `sender` chunk by chunk.
+async fn receive_file(
+    data: &mut Multipart,
+    crypto: &EncryptionKey,
+    compression: &CompressionConfig,
+    sender: Sender<StorageResult<Bytes>>,
+    available: i64,
+) -> Result<ReceivedFile, ApiError> {
+    let result = encrypt_fields(data, crypto, compression, &sender, available).await;
+
+    //? Make the storage backend discard whatever it has received so far
+    if result.is_err() {
+        let aborted = std::io::Error::other("The upload was aborted");
+        sender.send(Err(Box::new(aborted))).await.ok();
+    }
+
+    result
+}
+
+async fn encrypt_fields(
+    data: &mut Multipart,
+    crypto: &EncryptionKey,
+    config: &CompressionConfig,
+    sender: &Sender<StorageResult<Bytes>>,
+    available: i64,
+) -> Result<ReceivedFile, ApiError> {
+    let invalid = || ApiError::bad_request("Invalid file");
+    let mut received = None;
+
+    while let Some(mut field) = data.try_next().await.map_err(|_| invalid())? {
+        if field.name() != "file" || received.is_some() {
+            return Err(invalid());
+        }
+
+        let name = field
+            .content_disposition()
+            .get_filename()
+            .unwrap_or_default()
+            .to_string();
+        let mimetype = field.content_type().to_string();
+
+        let mut size: i64 = 0;
+        let mut encryptor = Encryptor::with_chunk_size(crypto, CHUNK_SIZE)?;
+
+        let compression = config.for_mimetype(&mimetype);
+        let level = config
+            .level
+            .unwrap_or_else(|| compression::default_level(compression));
+        let mut compressor = Compressor::new(compression, level)?;
+
+        let header = Header::new(CHUNK_SIZE as u32, None).compressed(compression);
+        send_chunk(sender, Bytes::copy_from_slice(&header.to_bytes())).await?;
+
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| invalid())?;
+            size += chunk.len() as i64;
 
-    files
-        .delete_one(doc! {"_id": file._id}, None)
+            if size >= available {
+                return Err(ApiError::PayloadTooLarge(
+                    "The file you are trying to upload would exceed your available quota"
+                        .to_string(),
+                ));
+            }
+
+            let chunk = match compressor {
+                Some(ref mut compressor) => compressor.update(&chunk)?,
+                None => chunk,
+            };
+
+            let sealed = encryptor.update(&chunk)?;
+
+            if !sealed.is_empty() {
+                send_chunk(sender, sealed).await?;
+            }
+        }
+
+        if let Some(compressor) = compressor {
+            let sealed = encryptor.update(&compressor.finish()?)?;
+
+            if !sealed.is_empty() {
+                send_chunk(sender, sealed).await?;
+            }
+        }
+
+        let sealed = encryptor.finish()?;
+        send_chunk(sender, sealed).await?;
+
+        received = Some(ReceivedFile {
+            name,
+            mimetype,
+            size,
+            compression,
+        });
+    }
+
+    received.ok_or_else(invalid)
+}
+
+async fn send_chunk(sender: &Sender<StorageResult<Bytes>>, chunk: Bytes) -> Result<(), ApiError> {
+    //? The receiver is only dropped when the storage backend gave up on the upload
+    sender
+        .send(Ok(chunk))
         .await
-        .unwrap();
+        .map_err(|_| ApiError::internal("the storage backend stopped receiving the upload"))
+}
+
+pub async fn delete_file(
+    request: HttpRequest,
+    data: Query<FileDeleteRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+
+    let file = find_linked(state, &data.id).await?.ok_or_else(|| {
+        ApiError::not_found("The specified file does not exist or your deletion key is invalid")
+    })?;
+
+    //? Files can be deleted with their deletion key, or with a token by their uploader or a moderator
+    match data.dkey {
+        Some(ref dkey) => {
+            if hash_string(dkey) != file.dkey {
+                return Err(ApiError::Unauthorized("Invalid deletion key".to_string()));
+            }
+        }
+        None => {
+            let requester = authorize(&request, Scopes::DELETE, Permissions::empty()).await?;
+            let own = requester.user._id == file.uploader;
+
+            if !(requester.permissions.contains(Permissions::DELETE_ANY)
+                || own && requester.permissions.contains(Permissions::DELETE_OWN))
+            {
+                return Err(ApiError::forbidden());
+            }
+        }
+    }
+
+    //? Purging deletes the blob before the record and refunds the quota only once, even when a
+    //? concurrent request or the sweeper gets to the file first
+    sweeper::purge(state.database.as_ref(), state.storage.as_ref(), &file).await?;
+
+    state.database.delete_file(file._id).await?;
 
     Ok(HttpResponse::NoContent().body(""))
 }
@@ -191,56 +445,730 @@ pub async fn delete_file(
 pub async fn get_file(
     request: HttpRequest,
     auth: Query<FileGetRequest>,
-    hash: Path<String>,
-) -> Result<HttpResponse, Error> {
-    let state = request.app_data::<AppState>().unwrap();
-    let files = state.database.collection::<File>("files");
-    let storage = state.storage.clone();
-
-    let hash = hash.into_inner();
-    let hash = hash.split('.').next().unwrap();
-
-    let file = match files.find_one(doc! {"hash": &hash}, None).await {
-        Ok(file) => {
-            if file.is_none() {
-                return Ok(HttpResponse::NotFound().body("The specified file does not exist"));
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let passphrase = passphrase(&request);
+
+    serve_file(request, auth, id, passphrase).await
+}
+
+/// Downloads a passphrase-protected file with the passphrase entered on the prompt page.
+pub async fn unlock_file(
+    request: HttpRequest,
+    auth: Query<FileGetRequest>,
+    id: Path<String>,
+    data: Form<FileUnlockRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let passphrase = data.into_inner().passphrase;
+
+    serve_file(request, auth, id, Some(passphrase)).await
+}
+
+async fn serve_file(
+    request: HttpRequest,
+    auth: Query<FileGetRequest>,
+    id: Path<String>,
+    passphrase: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+    let file = find_available(state, &id).await?;
+
+    let size = file.size as u64;
+
+    //? Every request for a download-limited file costs a download, so it is only ever served in
+    //? full and must not be kept by any cache
+    let limited = file.max_downloads.is_some();
+
+    //? Compressed files can only be decompressed from the start, so they are always served in
+    //? full, and as they are stored when the client accepts their encoding
+    let compressed = file.compression != Compression::None;
+    let encoding = file
+        .compression
+        .encoding()
+        .filter(|encoding| accepts_encoding(&request, encoding));
+
+    let etag = match encoding {
+        Some(encoding) => EntityTag::new_strong(format!("{}-{}", file.public_id(), encoding)),
+        None => EntityTag::new_strong(file.public_id().to_string()),
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(ETag(etag.clone()))
+        .insert_header(LastModified(SystemTime::from(file.created_at).into()))
+        .insert_header((
+            ACCEPT_RANGES,
+            if limited || compressed {
+                "none"
+            } else {
+                "bytes"
+            },
+        ));
+
+    if compressed {
+        response.insert_header((VARY, "Accept-Encoding"));
+    }
+
+    if let Some(encoding) = encoding {
+        response.insert_header((CONTENT_ENCODING, encoding));
+    }
+
+    //? The compressed length is not known until the whole file has been read
+    let length = match encoding {
+        Some(_) => None,
+        None => Some(size),
+    };
+
+    //? Unlocked content of a protected file is not left behind in the browser cache either
+    match limited || file.lock.is_some() {
+        true => response.insert_header(CacheControl(vec![CacheDirective::NoStore])),
+        false => response.insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(31_536_000),
+        ])),
+    };
+
+    let key = base64::decode_config(&auth.key, URL_SAFE_NO_PAD)
+        .map_err(|_| ApiError::bad_request("The key is not valid base64"))?;
+    let nonce = base64::decode_config(&auth.nonce, URL_SAFE_NO_PAD)
+        .map_err(|_| ApiError::bad_request("The nonce is not valid base64"))?;
+
+    let key = match (&file.lock, passphrase) {
+        (None, _) => key,
+        (Some(_), None) => {
+            return prompt(state, &request, "This file is protected by a passphrase");
+        }
+        (Some(lock), Some(passphrase)) => {
+            let lock = lock.clone();
+
+            match web::block(move || passphrases::unlock(&passphrase, &key, &lock)).await? {
+                Some(key) => key,
+                None => return prompt(state, &request, "The passphrase is not correct"),
             }
-            file.unwrap()
         }
-        Err(_) => {
-            return Ok(
-                HttpResponse::InternalServerError().body("Failed to retrieve file from database")
-            );
+    };
+
+    let (filename, mimetype) = match file.sealed {
+        true => match (
+            open_text(&key, &file.filename),
+            open_text(&key, &file.mimetype),
+        ) {
+            (Some(filename), Some(mimetype)) => (filename, mimetype),
+            _ => {
+                return Err(ApiError::bad_request(
+                    "The file could not be decrypted with the given key and nonce",
+                ));
+            }
+        },
+        false => (file.filename.clone(), file.mimetype.clone()),
+    };
+
+    let crypto = EncryptionKey { key, nonce };
+    let storage_key = file.storage_key();
+    let not_modified = is_not_modified(&request, &etag, &file);
+
+    //? Neither the ETag nor the metadata is confirmed to requests without the right key, which
+    //? unsealed files can only tell by decrypting their first chunk
+    if !file.sealed && (not_modified || request.method() == Method::HEAD) {
+        let stream = state
+            .storage
+            .get_stream(&storage_key)
+            .await
+            .map_err(|_| ApiError::not_found("The specified file does not exist"))?;
+        let _ = peek(Box::pin(container::open_stream(crypto.clone(), stream))).await?;
+    }
+
+    if not_modified {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+
+    if request.method() == Method::HEAD {
+        return Ok(head(response, &filename, &mimetype, length));
+    }
+
+    response
+        .content_type(mimetype.clone())
+        .append_header(("Content-Disposition", format!("filename=\"{}\"", filename)));
+
+    let ranges = match requested_ranges(&request, &etag, &file) {
+        Some(ranges) if !limited && !compressed => ranges,
+        _ => {
+            let stream = state
+                .storage
+                .get_stream(&storage_key)
+                .await
+                .map_err(|_| ApiError::not_found("The specified file does not exist"))?;
+            let mut body = peek(Box::pin(container::open_stream(crypto, stream))).await?;
+
+            if encoding.is_none() {
+                body = decompress_stream(file.compression, body);
+            }
+
+            if limited {
+                body = claim_download(state, &file, body).await?;
+            }
+
+            if let Some(length) = length {
+                response.no_chunking(length);
+            }
+
+            return Ok(response.streaming(into_body(body)));
         }
     };
 
-    let file_bits = match storage
-        .get_file(file.uploader.to_hex().as_str(), &file.hash)
+    if ranges.is_empty() {
+        return Err(ApiError::RangeNotSatisfiable(size));
+    }
+
+    let mut parts = Vec::with_capacity(ranges.len());
+
+    for &(start, end) in &ranges {
+        let part = container::open_range(
+            state.storage.as_ref(),
+            &storage_key,
+            crypto.clone(),
+            size,
+            start,
+            end,
+        )
+        .await
+        .map_err(|_| ApiError::not_found("The specified file does not exist"))?;
+
+        parts.push(part);
+    }
+
+    response.status(StatusCode::PARTIAL_CONTENT);
+
+    if let [(start, end)] = ranges[..] {
+        response.insert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((start, end)),
+            instance_length: Some(size),
+        }));
+
+        let body = peek(parts.remove(0)).await?;
+
+        return Ok(response
+            .no_chunking(end - start + 1)
+            .streaming(into_body(body)));
+    }
+
+    //? Several ranges are sent as a multipart/byteranges body, one part per range
+    let boundary = Uuid::new_v4().simple().to_string();
+    let mut length = 0;
+    let mut body: Vec<ByteStream> = Vec::with_capacity(parts.len() * 2 + 1);
+
+    for (&(start, end), part) in ranges.iter().zip(parts) {
+        let head = format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, mimetype, start, end, size
+        );
+
+        let part = match body.is_empty() {
+            true => peek(part).await?,
+            false => part,
+        };
+
+        length += head.len() as u64 + (end - start + 1) + 2;
+        body.push(Box::pin(stream::once(async move { Ok(Bytes::from(head)) })));
+        body.push(Box::pin(
+            part.chain(stream::once(async { Ok(Bytes::from_static(b"\r\n")) })),
+        ));
+    }
+
+    let tail = format!("--{}--\r\n", boundary);
+    length += tail.len() as u64;
+    body.push(Box::pin(stream::once(async move { Ok(Bytes::from(tail)) })));
+
+    Ok(response
+        .content_type(format!("multipart/byteranges; boundary={}", boundary))
+        .no_chunking(length)
+        .streaming(into_body(Box::pin(stream::iter(body).flatten()))))
+}
+
+/// Looks up a file that can still be downloaded, answering 410 Gone for expired ones and ones
+/// that have reached their download limit.
+pub async fn find_available(state: &AppState, id: &str) -> Result<File, ApiError> {
+    //? Links may carry the original extension, e.g. `/abcd1234.png`
+    let id = id.split('.').next().unwrap_or_default();
+
+    let file = find_linked(state, id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("The specified file does not exist"))?;
+
+    if file.purged || file.is_expired() {
+        return Err(ApiError::Gone("The specified file has expired".to_string()));
+    }
+
+    if file.is_exhausted() {
+        return Err(ApiError::Gone(
+            "The specified file has reached its download limit".to_string(),
+        ));
+    }
+
+    Ok(file)
+}
+
+/// Finds the file a link refers to, whether its ID is spelled as-is or as a slug.
+async fn find_linked(state: &AppState, id: &str) -> Result<Option<File>, ApiError> {
+    //? A random ID can also read as a slug, e.g. `PineLark`, so the literal ID is tried first
+    if let Some(file) = state.database.find_file(id).await? {
+        return Ok(Some(file));
+    }
+
+    let decoded = slugs::decode(id);
+
+    match decoded == id {
+        true => Ok(None),
+        false => Ok(state.database.find_file(&decoded).await?),
+    }
+}
+
+/// Sends a file still encrypted, exactly as it is stored, for clients that decrypt it themselves
+/// so the key never has to reach the server.
+pub async fn get_raw_file(
+    request: HttpRequest,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+    let file = find_available(state, &id).await?;
+
+    //? Fetching the ciphertext takes no key, so it can not be allowed to use up downloads
+    if file.max_downloads.is_some() {
+        return Err(ApiError::Forbidden(
+            "Download-limited files can only be downloaded through their regular link".to_string(),
+        ));
+    }
+
+    let body = state
+        .storage
+        .get_stream(&file.storage_key())
         .await
+        .map_err(|_| ApiError::not_found("The specified file does not exist"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(31_536_000),
+        ]))
+        .streaming(into_body(body)))
+}
+
+/// Finishes the response to a `HEAD` request, which describes the file without sending it.
+fn head(
+    mut response: HttpResponseBuilder,
+    filename: &str,
+    mimetype: &str,
+    length: Option<u64>,
+) -> HttpResponse {
+    response
+        .content_type(mimetype)
+        .append_header(("Content-Disposition", format!("filename=\"{}\"", filename)));
+
+    if let Some(length) = length {
+        response.no_chunking(length);
+    }
+
+    response.streaming(stream::empty::<Result<Bytes, std::io::Error>>())
+}
+
+/// Whether the `Accept-Encoding` header allows responding with `encoding`.
+fn accepts_encoding(request: &HttpRequest, encoding: &str) -> bool {
+    let header = match request
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|header| header.to_str().ok())
     {
-        Ok(bytes) => {
-            let key = base64::decode_config(&auth.key, URL_SAFE_NO_PAD).unwrap();
-            let nonce = base64::decode_config(&auth.nonce, URL_SAFE_NO_PAD).unwrap();
+        Some(header) => header,
+        None => return false,
+    };
+
+    header.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+
+        //? `q=0` explicitly refuses an encoding
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+    })
+}
+
+/// The passphrase sent in the `X-Passphrase` header, if any.
+fn passphrase(request: &HttpRequest) -> Option<String> {
+    let header = request.headers().get(PASSPHRASE_HEADER)?;
+
+    String::from_utf8(header.as_bytes().to_vec()).ok()
+}
+
+/// Asks for the passphrase of a protected file, with a form for browsers and an error for
+/// API clients, which send it in the `X-Passphrase` header instead.
+fn prompt(
+    state: &AppState,
+    request: &HttpRequest,
+    message: &str,
+) -> Result<HttpResponse, ApiError> {
+    let browser = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    if !browser {
+        return Err(ApiError::Unauthorized(message.to_string()));
+    }
+
+    let mut context = Context::new();
+    context.insert("message", message);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+
+    let html = state.tera.render("passphrase.html", &context)?;
+
+    Ok(HttpResponse::Unauthorized()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .content_type("text/html")
+        .body(html))
+}
+
+/// Counts a download of a download-limited file, failing once its limit has been reached.
+///
+/// The counter is only incremented while it is below the limit, so concurrent requests can
+/// never be served more often than allowed. The last permitted download removes the file once
+/// its body has been sent or the client went away.
+async fn claim_download(
+    state: &AppState,
+    file: &File,
+    body: ByteStream,
+) -> Result<ByteStream, ApiError> {
+    let claimed = state
+        .database
+        .claim_download(file._id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Gone("The specified file has reached its download limit".to_string())
+        })?;
+
+    if !claimed.is_exhausted() {
+        return Ok(body);
+    }
+
+    let burn = Burn(Some((state.clone(), claimed)));
 
-            let crypto = EncryptionKey { key, nonce };
+    //? The guard lives as long as the body, which is dropped once the response is done
+    Ok(Box::pin(body.map(move |chunk| {
+        let _ = &burn;
+        chunk
+    })))
+}
 
-            match decrypt_bytes(&crypto, &bytes) {
-                Ok(dbytes) => dbytes,
-                Err(_) => {
-                    return Ok(HttpResponse::InternalServerError().body("Failed to decrypt file"));
+/// Removes a file that has used up its downloads when dropped.
+struct Burn(Option<(AppState, File)>);
+
+impl Drop for Burn {
+    fn drop(&mut self) {
+        if let Some((state, file)) = self.0.take() {
+            tokio::spawn(async move {
+                if let Err(e) = burn(&state, &file).await {
+                    error!(
+                        "Failed to remove exhausted file {}: {}",
+                        file.public_id(),
+                        e
+                    );
                 }
-            }
+            });
         }
-        Err(_) => {
-            return Ok(HttpResponse::NotFound().body("The specified file does not exist"));
+    }
+}
+
+async fn burn(state: &AppState, file: &File) -> Result<(), StorageError> {
+    if sweeper::purge(state.database.as_ref(), state.storage.as_ref(), file).await? {
+        state.database.delete_file(file._id).await?;
+    }
+
+    Ok(())
+}
+
+/// Maximum number of ranges served from a single request, larger sets are answered in full.
+const MAX_RANGES: usize = 16;
+
+/// Evaluates `If-None-Match` and, in its absence, `If-Modified-Since`.
+fn is_not_modified(request: &HttpRequest, etag: &EntityTag, file: &File) -> bool {
+    if let Ok(if_none_match) = IfNoneMatch::parse(request) {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+
+    match IfModifiedSince::parse(request) {
+        Ok(IfModifiedSince(since)) => is_unchanged_since(file, since),
+        Err(_) => false,
+    }
+}
+
+fn is_unchanged_since(file: &File, date: HttpDate) -> bool {
+    //? HTTP dates only have a precision of one second
+    match SystemTime::from(date).duration_since(UNIX_EPOCH) {
+        Ok(since) => file.created_at.timestamp() <= since.as_secs() as i64,
+        Err(_) => false,
+    }
+}
+
+/// Resolves the `Range` header into inclusive byte ranges.
+///
+/// Returns `None` when the whole file should be served, either because no usable range was
+/// requested or because `If-Range` no longer matches, and an empty list when none of the
+/// requested ranges can be satisfied.
+fn requested_ranges(
+    request: &HttpRequest,
+    etag: &EntityTag,
+    file: &File,
+) -> Option<Vec<(u64, u64)>> {
+    let specs = match Range::parse(request) {
+        Ok(Range::Bytes(specs)) if specs.len() <= MAX_RANGES => specs,
+        _ => return None,
+    };
+
+    let if_range = match IfRange::parse(request) {
+        Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Ok(IfRange::Date(date)) => is_unchanged_since(file, date),
+        Err(_) => request.headers().get(IF_RANGE).is_none(),
+    };
+
+    if !if_range {
+        return None;
+    }
+
+    Some(
+        specs
+            .iter()
+            .filter_map(|spec| spec.to_satisfiable_range(file.size as u64))
+            .collect(),
+    )
+}
+
+/// Pulls the first chunk out of a decrypting stream so a wrong key is reported before any
+/// response headers are sent.
+async fn peek<S>(mut stream: S) -> Result<ByteStream, ApiError>
+where
+    S: Stream<Item = StorageResult<Bytes>> + Send + Unpin + 'static,
+{
+    let first = match stream.next().await {
+        Some(Ok(bytes)) => bytes,
+        Some(Err(_)) => {
+            return Err(ApiError::bad_request(
+                "The file could not be decrypted with the given key and nonce",
+            ));
         }
+        None => Bytes::new(),
     };
 
-    Ok(HttpResponse::Ok()
-        .content_type(file.mimetype.clone())
-        .append_header((
-            "Content-Disposition",
-            format!("filename=\"{}\"", file.filename),
+    Ok(Box::pin(
+        stream::once(async move { Ok::<_, StorageError>(first) }).chain(stream),
+    ))
+}
+
+/// Converts storage errors into ones actix can report while streaming a response body.
+fn into_body(stream: ByteStream) -> impl Stream<Item = std::io::Result<Bytes>> {
+    stream.map_err(std::io::Error::other)
+}
+
+#[test]
+fn test_file_lifetime() {
+    let options = |expires_in| FileUploadRequest {
+        expires_in,
+        expires_at: None,
+        max_downloads: None,
+        slug: None,
+        fragment: false,
+    };
+
+    assert_eq!(file_lifetime(&options(None), None, None).unwrap(), None);
+    assert_eq!(
+        file_lifetime(&options(Some(60)), Some(3600), None).unwrap(),
+        Some(60)
+    );
+    assert_eq!(
+        file_lifetime(&options(None), Some(3600), Some(86400)).unwrap(),
+        Some(3600)
+    );
+    assert_eq!(
+        file_lifetime(&options(None), Some(3600), None).unwrap(),
+        Some(3600)
+    );
+    assert!(file_lifetime(&options(Some(86400)), Some(3600), None).is_err());
+    assert!(file_lifetime(&options(Some(0)), None, None).is_err());
+    assert_eq!(
+        file_lifetime(&options(None), None, Some(i64::MAX)).unwrap(),
+        Some(MAX_LIFETIME)
+    );
+    assert_eq!(
+        file_lifetime(&options(None), Some(i64::MAX), None).unwrap(),
+        Some(MAX_LIFETIME)
+    );
+}
+
+/// App state backed by an in-memory SQLite database and storage, with the built-in roles and
+/// an `Alice` user whose token is `token`.
+#[cfg(test)]
+async fn test_state() -> (AppState, crate::structs::users::User) {
+    use crate::{
+        modules::{
+            config::{Config, DatabaseConfig, DatabaseKind},
+            database::{sql::SqlDatabase, DatabaseBackend, UserRepository},
+            roles,
+            storage::memory::MemoryStorage,
+        },
+        structs::users::User,
+    };
+    use std::sync::Arc;
+
+    let database = SqlDatabase::connect(&DatabaseConfig {
+        kind: DatabaseKind::Sqlite,
+        uri: "sqlite::memory:".to_string(),
+        db_name: String::new(),
+    })
+    .await
+    .unwrap();
+    database.setup().await.unwrap();
+    roles::setup(&database).await.unwrap();
+
+    let user = User::from("Alice", "hash", "alice@example.com", "token");
+    database.insert_user(&user).await.unwrap();
+
+    let state = AppState {
+        config: Config::default(),
+        database: Arc::new(database),
+        storage: Arc::new(MemoryStorage::new()),
+        tera: tera::Tera::default(),
+    };
+
+    (state, user)
+}
+
+#[actix_web::test]
+async fn test_identical_uploads() {
+    use actix_web::{test, App};
+
+    let (state, user) = test_state().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .route("/api/v1/files", web::post().to(upload_file))
+            .route("/api/v1/files/delete", web::get().to(delete_file))
+            .route("/{id}", web::get().to(get_file)),
+    )
+    .await;
+
+    let mut uploads = Vec::new();
+
+    for _ in 0..2 {
+        let request = test::TestRequest::post()
+            .uri("/api/v1/files")
+            .insert_header(("Authorization", "token"))
+            .insert_header(("Content-Type", "multipart/form-data; boundary=mgo"))
+            .set_payload(
+                "--mgo\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                Content-Type: text/plain\r\n\r\nHello World\r\n--mgo--\r\n",
+            )
+            .to_request();
+        let upload: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        uploads.push(upload);
+    }
+
+    let download = |upload: &serde_json::Value| {
+        test::TestRequest::get()
+            .uri(&format!(
+                "/{}?key={}&nonce={}",
+                upload["id"].as_str().unwrap(),
+                upload["key"].as_str().unwrap(),
+                upload["nonce"].as_str().unwrap()
+            ))
+            .to_request()
+    };
+
+    for upload in &uploads {
+        let body = test::call_and_read_body(&app, download(upload)).await;
+        assert_eq!(body, "Hello World");
+
+        //? A content hash would tell anyone with the database which uploads are identical
+        let file = state
+            .database
+            .find_file(upload["id"].as_str().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(file.hash.is_empty());
+    }
+
+    let user = state.database.find_user(user._id).await.unwrap().unwrap();
+    assert_eq!(user.quota.used, 22);
+
+    //? Deleting one copy leaves the other one readable
+    let request = test::TestRequest::get()
+        .uri(&format!(
+            "/api/v1/files/delete?id={}&dkey={}",
+            uploads[0]["id"].as_str().unwrap(),
+            uploads[0]["dkey"].as_str().unwrap()
         ))
-        .body(file_bits))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::NO_CONTENT
+    );
+
+    let body = test::call_and_read_body(&app, download(&uploads[1])).await;
+    assert_eq!(body, "Hello World");
+
+    let user = state.database.find_user(user._id).await.unwrap().unwrap();
+    assert_eq!(user.quota.used, 11);
+}
+
+#[actix_web::test]
+async fn test_literal_ids() {
+    let (state, _) = test_state().await;
+
+    let file = |id: &str| File {
+        _id: ObjectId::new(),
+        id: Some(id.to_string()),
+        filename: id.to_string(),
+        mimetype: "text/plain".to_string(),
+        uploader: ObjectId::new(),
+        hash: id.to_string(),
+        dkey: String::new(),
+        size: 0,
+        blob: None,
+        created_at: Utc::now(),
+        expires_at: None,
+        purged: false,
+        max_downloads: None,
+        downloads: 0,
+        lock: None,
+        sealed: false,
+        vault_key: None,
+        compression: Compression::None,
+    };
+
+    //? `PineLark` is a valid random ID as well as the word slug of another one
+    let decoded = slugs::decode("PineLark");
+    assert_ne!(decoded, "PineLark");
+
+    let literal = file("PineLark");
+    state.database.insert_file(&literal).await.unwrap();
+    state.database.insert_file(&file(&decoded)).await.unwrap();
+
+    let found = find_available(&state, "PineLark.png").await.unwrap();
+    assert_eq!(found.filename, "PineLark");
+
+    state.database.delete_file(literal._id).await.unwrap();
+
+    let found = find_available(&state, "PineLark").await.unwrap();
+    assert_eq!(found.filename, decoded);
+    assert!(find_available(&state, "PineOwl").await.is_err());
 }
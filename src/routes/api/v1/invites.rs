@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use actix_web::{
+    web::{Form, Path},
+    HttpRequest, HttpResponse, Result,
+};
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    modules::hashing::hash_string,
+    routes::{api::v1::auth::authorize, error::ApiError, state},
+    structs::{
+        invites::{Invite, InviteCreateRequest},
+        users::User,
+        Permissions, Scopes,
+    },
+};
+
+/// Longest time an invite can stay valid for, in seconds.
+const MAX_EXPIRY: i64 = 60 * 60 * 24 * 365;
+
+fn describe(invite: &Invite) -> Value {
+    json!({
+        "id": invite._id.to_hex(),
+        "created_by": invite.created_by.to_hex(),
+        "uses": invite.uses,
+        "max_uses": invite.max_uses,
+        "role": invite.role,
+        "quota": invite.quota,
+        "created_at": invite.created_at.to_rfc3339(),
+        "expires_at": invite.expires_at.map(|date| date.to_chrono().to_rfc3339()),
+    })
+}
+
+pub async fn list_invites(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+    let found = state.database.list_invites().await?;
+
+    Ok(HttpResponse::Ok().json(found.iter().map(describe).collect::<Vec<_>>()))
+}
+
+pub async fn create_invite(
+    request: HttpRequest,
+    data: Form<InviteCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    if matches!(data.max_uses, Some(uses) if uses < 1) {
+        return Err(ApiError::bad_request(
+            "An invite must allow at least one use",
+        ));
+    }
+
+    //? Handing out a non-default quota is a quota decision as much as a user one
+    if data.quota.is_some() && !requester.permissions.contains(Permissions::MANAGE_QUOTAS) {
+        return Err(ApiError::forbidden());
+    }
+
+    if matches!(data.quota, Some(quota) if quota < 0) {
+        return Err(ApiError::bad_request("The quota must not be negative"));
+    }
+
+    if let Some(ref role) = data.role {
+        let role = state
+            .database
+            .find_role(role)
+            .await?
+            .ok_or_else(|| ApiError::bad_request("The specified role does not exist"))?;
+
+        //? An invite can not grant more than its creator holds
+        if !requester.permissions.contains(role.permissions) {
+            return Err(ApiError::forbidden());
+        }
+    }
+
+    let expires_at = match data.expires_in {
+        Some(seconds) if seconds > 0 && seconds <= MAX_EXPIRY => Some(bson::DateTime::from_chrono(
+            Utc::now() + Duration::seconds(seconds),
+        )),
+        Some(_) => {
+            return Err(ApiError::bad_request(
+                "The expiry must be between 1 second and 1 year",
+            ));
+        }
+        None => None,
+    };
+
+    let code = User::generate_token();
+
+    let invite = Invite {
+        _id: ObjectId::new(),
+        hash: hash_string(&code),
+        created_by: requester.user._id,
+        uses: 0,
+        max_uses: data.max_uses,
+        role: data.role.clone(),
+        quota: data.quota,
+        created_at: Utc::now(),
+        expires_at,
+    };
+
+    state.database.insert_invite(&invite).await?;
+
+    let mut body = describe(&invite);
+    body["code"] = json!(code);
+
+    Ok(HttpResponse::Created().json(body))
+}
+
+pub async fn delete_invite(
+    request: HttpRequest,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    let _id = ObjectId::from_str(&id)
+        .map_err(|_| ApiError::bad_request("The specified id is not valid"))?;
+
+    if !state.database.delete_invite(_id).await? {
+        return Err(ApiError::not_found("The specified invite does not exist"));
+    }
+
+    Ok(HttpResponse::Ok().body("Invite deleted"))
+}
@@ -1,2 +1,8 @@
+pub mod auth;
 pub mod files;
+pub mod invites;
+pub mod roles;
+pub mod stats;
+pub mod tokens;
 pub mod users;
+pub mod vault;
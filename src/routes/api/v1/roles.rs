@@ -0,0 +1,115 @@
+use actix_web::{
+    web::{Form, Path},
+    HttpRequest, HttpResponse, Result,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    modules::roles::{ADMIN_ROLE, DEFAULT_ROLE},
+    routes::{
+        api::v1::{auth::authorize, files::MAX_LIFETIME},
+        error::ApiError,
+        state,
+    },
+    structs::{
+        roles::{Role, RoleRequest},
+        Permissions, Scopes,
+    },
+};
+
+fn describe(role: &Role) -> Value {
+    json!({
+        "name": role._id,
+        "permissions": role.permissions.names(),
+        "max_file_lifetime": role.max_file_lifetime,
+    })
+}
+
+pub async fn list_roles(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+    let found = state.database.list_roles().await?;
+
+    Ok(HttpResponse::Ok().json(found.iter().map(describe).collect::<Vec<_>>()))
+}
+
+/// Creates the role or replaces its permissions.
+pub async fn put_role(
+    request: HttpRequest,
+    name: Path<String>,
+    data: Form<RoleRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    let name = name.into_inner();
+
+    if name == ADMIN_ROLE {
+        return Err(ApiError::bad_request("The admin role can not be changed"));
+    }
+
+    if name.trim().is_empty() || name.len() > 64 {
+        return Err(ApiError::bad_request("The role name is not valid"));
+    }
+
+    let permissions = Permissions::from_names(&data.permissions)
+        .ok_or_else(|| ApiError::bad_request("The specified permissions are not valid"))?;
+
+    if matches!(data.max_file_lifetime, Some(lifetime) if lifetime <= 0 || lifetime > MAX_LIFETIME)
+    {
+        return Err(ApiError::bad_request(format!(
+            "The maximum file lifetime must be between 1 and {} seconds",
+            MAX_LIFETIME
+        )));
+    }
+
+    //? Nobody can hand out, or take away, permissions they do not hold themselves
+    let existing = state
+        .database
+        .find_role(&name)
+        .await?
+        .map(|role| role.permissions)
+        .unwrap_or_else(Permissions::empty);
+
+    if !requester.permissions.contains(permissions | existing) {
+        return Err(ApiError::forbidden());
+    }
+
+    state
+        .database
+        .put_role(&name, permissions, data.max_file_lifetime)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "name": name,
+        "permissions": permissions.names(),
+        "max_file_lifetime": data.max_file_lifetime,
+    })))
+}
+
+pub async fn delete_role(
+    request: HttpRequest,
+    name: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    if *name == DEFAULT_ROLE || *name == ADMIN_ROLE {
+        return Err(ApiError::bad_request("Built-in roles can not be deleted"));
+    }
+
+    if state.database.count_users_with_role(&name).await? > 0 {
+        return Err(ApiError::Conflict(
+            "The role is still assigned to users".to_string(),
+        ));
+    }
+
+    if !state.database.delete_role(&name).await? {
+        return Err(ApiError::not_found("The specified role does not exist"));
+    }
+
+    Ok(HttpResponse::Ok().body("Role deleted"))
+}
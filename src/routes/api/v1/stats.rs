@@ -0,0 +1,23 @@
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde_json::json;
+
+use crate::{
+    routes::{api::v1::auth::authorize, error::ApiError, state},
+    structs::{Permissions, Scopes},
+};
+
+/// Totals across the whole instance.
+pub async fn get_stats(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::READ, Permissions::VIEW_STATS).await?;
+
+    let state = state(&request)?;
+
+    let user_count = state.database.count_users().await?;
+    let (files, bytes) = state.database.file_totals().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": user_count,
+        "files": files,
+        "bytes": bytes,
+    })))
+}
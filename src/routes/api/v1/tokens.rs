@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+use actix_web::{
+    web::{Form, Path},
+    HttpRequest, HttpResponse, Result,
+};
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    routes::{api::v1::auth::authorize, error::ApiError, state},
+    structs::{
+        tokens::{ApiToken, TokenCreateRequest},
+        Permissions, Scopes,
+    },
+};
+
+/// Longest lifetime a token can be created with, in seconds.
+const MAX_EXPIRY: i64 = 60 * 60 * 24 * 365 * 10;
+
+fn describe(token: &ApiToken) -> Value {
+    json!({
+        "id": token._id.to_hex(),
+        "name": token.name,
+        "scopes": token.scopes.names(),
+        "created_at": token.created_at.to_rfc3339(),
+        "last_used_at": token.last_used_at.map(|date| date.to_chrono().to_rfc3339()),
+        "expires_at": token.expires_at.map(|date| date.to_chrono().to_rfc3339()),
+    })
+}
+
+pub async fn list_tokens(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::READ, Permissions::empty()).await?;
+
+    let state = state(&request)?;
+    let found = state.database.list_tokens(requester.user._id).await?;
+
+    Ok(HttpResponse::Ok().json(found.iter().map(describe).collect::<Vec<_>>()))
+}
+
+pub async fn create_token(
+    request: HttpRequest,
+    data: Form<TokenCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::TOKENS, Permissions::empty()).await?;
+
+    let state = state(&request)?;
+
+    if data.name.trim().is_empty() {
+        return Err(ApiError::bad_request("The token name must not be empty"));
+    }
+
+    let scopes = match Scopes::from_names(&data.scopes) {
+        Some(scopes) if !scopes.is_empty() => scopes,
+        _ => return Err(ApiError::bad_request("The specified scopes are not valid")),
+    };
+
+    //? A token can never be used to mint a more powerful one
+    if !requester.scopes.contains(scopes) {
+        return Err(ApiError::Forbidden(
+            "The token is missing a required scope".to_string(),
+        ));
+    }
+
+    let expires_at = match data.expires_in {
+        Some(seconds) if seconds > 0 && seconds <= MAX_EXPIRY => {
+            Some(Utc::now() + Duration::seconds(seconds))
+        }
+        Some(_) => {
+            return Err(ApiError::bad_request(
+                "The expiry must be between 1 second and 10 years",
+            ));
+        }
+        None => None,
+    };
+
+    //? Tokens never outlive the token they were created with
+    let expires_at = match (expires_at, requester.expires_at) {
+        (Some(expires_at), Some(parent)) => Some(expires_at.min(parent)),
+        (expires_at, parent) => expires_at.or(parent),
+    };
+
+    let (api_token, token) =
+        ApiToken::new(requester.user._id, data.name.trim(), scopes, expires_at);
+
+    state.database.insert_token(&api_token).await?;
+
+    let mut body = describe(&api_token);
+    body["token"] = json!(token);
+
+    Ok(HttpResponse::Created().json(body))
+}
+
+pub async fn revoke_token(
+    request: HttpRequest,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::empty(), Permissions::empty()).await?;
+
+    let state = state(&request)?;
+
+    let _id = ObjectId::from_str(&id)
+        .map_err(|_| ApiError::bad_request("The specified id is not valid"))?;
+
+    //? Any token may revoke itself, only one with the `tokens` scope may revoke the others
+    if requester.token != Some(_id) && !requester.scopes.contains(Scopes::TOKENS) {
+        return Err(ApiError::Forbidden(
+            "The token is missing a required scope".to_string(),
+        ));
+    }
+
+    if !state.database.delete_token(_id, requester.user._id).await? {
+        return Err(ApiError::not_found("The specified token does not exist"));
+    }
+
+    Ok(HttpResponse::Ok().body("Token revoked"))
+}
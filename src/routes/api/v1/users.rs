@@ -1,205 +1,330 @@
 use std::str::FromStr;
 
 use actix_web::{
-    web::{Form, Header},
-    Error, HttpRequest, HttpResponse, Result,
+    web::{self, Form, Path, Query},
+    HttpRequest, HttpResponse, Result,
 };
 
-use bson::{doc, oid::ObjectId};
+use bson::oid::ObjectId;
 use serde_json::json;
 
 use crate::{
-    modules::storage::Storage,
+    modules::{
+        config::RegistrationMode,
+        invites,
+        passwords::hash_password,
+        roles::ADMIN_ROLE,
+        storage::user_prefix,
+        validation::{validate_email, validate_password, validate_username, FieldError},
+    },
+    routes::{api::v1::auth::authorize, error::ApiError, state},
     structs::{
-        files::File,
-        users::{User, UserCreateRequest, UserIdRequest},
-        AuthorizationHeader, Privileges,
+        users::{User, UserCreateRequest, UserListRequest, UserResponse, UserUpdateRequest},
+        Permissions, Scopes,
     },
     AppState,
 };
 
+const DEFAULT_PAGE_SIZE: u64 = 25;
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// Registers a new account according to the configured registration mode.
+///
+/// The first account on a fresh install is always accepted and given the admin role.
 pub async fn create_user(
     request: HttpRequest,
     data: Form<UserCreateRequest>,
-) -> Result<HttpResponse, Error> {
-    let state = request.app_data::<AppState>().unwrap();
-    let users = state.database.collection::<User>("users");
-    let storage = state.storage.clone();
-
-    let token = User::generate_token();
-    let user = User::from(&data.username, &data.password, &data.email, &token.clone());
+) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
 
-    let result = users.insert_one(&user, None).await;
+    let empty = state.database.count_users().await? == 0;
 
-    match storage {
-        Storage::Local(ref storage) => {
-            let path = format!("{}/{}", storage, user._id.to_hex());
-            tokio::fs::create_dir_all(&path).await?;
-        }
-        Storage::S3(ref _storage) => {
-            todo!("S3 storage");
+    if !empty {
+        match (state.config.registration.mode, &data.invite) {
+            (RegistrationMode::Closed, _) => {
+                return Err(ApiError::Forbidden("Registration is closed".to_string()));
+            }
+            (RegistrationMode::Invite, None) => {
+                return Err(ApiError::Forbidden(
+                    "An invite code is required to register".to_string(),
+                ));
+            }
+            _ => {}
         }
     }
 
-    if result.is_err() {
-        return Ok(HttpResponse::InternalServerError().body("Internal Server Error"));
+    let errors: Vec<FieldError> = [
+        validate_username(&data.username),
+        validate_email(&data.email),
+        validate_password(&data.password, &data.username, &state.config.passwords),
+    ]
+    .into_iter()
+    .filter_map(Result::err)
+    .collect();
+
+    if !errors.is_empty() {
+        return Err(ApiError::Invalid(errors));
     }
 
-    Ok(HttpResponse::Created().json(json!({ "token": token })))
-}
+    let config = state.config.passwords.clone();
+    let password = data.password.clone();
 
-pub async fn get_user(
-    request: HttpRequest,
-    data: Form<UserIdRequest>,
-    headers: &Header<AuthorizationHeader>,
-) -> Result<HttpResponse, Error> {
-    if headers.authorization.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
-    }
+    let password = web::block(move || hash_password(&password, &config))
+        .await?
+        .map_err(|e| ApiError::internal(format!("password hashing: {}", e)))?;
+
+    //? Counting alone would let two registrations racing on an empty database both become
+    //? administrators, only the one that claims the bootstrap marker does
+    let first = empty && state.database.claim_bootstrap().await?;
 
-    let state = request.app_data::<AppState>().unwrap();
-    let users = state.database.collection::<User>("users");
+    if empty && !first {
+        return Err(ApiError::Conflict(
+            "Another account is being set up, please try again".to_string(),
+        ));
+    }
 
-    let auth_token = headers.authorization.clone().unwrap();
+    let invite = match data.invite {
+        Some(ref code) if !first => Some(
+            invites::redeem(state.database.as_ref(), code)
+                .await?
+                .ok_or_else(|| {
+                    ApiError::Forbidden("The invite code is invalid or expired".to_string())
+                })?,
+        ),
+        _ => None,
+    };
 
-    let requester = users
-        .find_one(doc! {"token": auth_token}, None)
-        .await
-        .unwrap();
+    let token = User::generate_token();
+    let mut user = User::from(&data.username, &password, &data.email, &token.clone());
 
-    if requester.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    if first {
+        user.role = ADMIN_ROLE.to_string();
     }
 
-    let requester = requester.unwrap();
+    if let Some(ref invite) = invite {
+        if let Some(ref role) = invite.role {
+            user.role = role.clone();
+        }
 
-    if !requester.privileges.contains(Privileges::ADMIN) {
-        return Ok(HttpResponse::Forbidden().body("Forbidden"));
+        if let Some(quota) = invite.quota {
+            user.quota.available = quota;
+        }
     }
 
-    let _id = match ObjectId::from_str(&data.id) {
-        Ok(id) => id,
-        Err(_) => {
-            return Ok(HttpResponse::BadRequest().body("The specfiied id is not valid"));
+    if let Err(e) = state.database.insert_user(&user).await {
+        if let Some(invite) = invite {
+            state.database.restore_invite(invite._id).await.ok();
         }
-    };
 
-    let user = users.find_one(doc! { "_id": _id }, None).await.unwrap();
+        if first {
+            state.database.release_bootstrap().await.ok();
+        }
 
-    if user.is_none() {
-        return Ok(HttpResponse::NotFound().body("Not Found"));
+        return Err(e.into());
     }
 
-    Ok(HttpResponse::Ok().json(user.unwrap()))
+    Ok(HttpResponse::Created().json(json!({ "token": token })))
 }
 
-///
+/// Loads the user referred to by the `{id}` path segment.
+async fn find_user(request: &HttpRequest, id: &str) -> Result<User, ApiError> {
+    let state = state(request)?;
+
+    let _id = ObjectId::from_str(id)
+        .map_err(|_| ApiError::bad_request("The specified id is not valid"))?;
+
+    state
+        .database
+        .find_user(_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("The specified user does not exist"))
+}
+
+pub async fn list_users(
+    request: HttpRequest,
+    query: Query<UserListRequest>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let (found, total) = state
+        .database
+        .list_users(
+            &query,
+            page.saturating_sub(1).saturating_mul(per_page),
+            per_page,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": found.iter().map(UserResponse::from).collect::<Vec<_>>(),
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    })))
+}
+
+pub async fn get_user(request: HttpRequest, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let user = find_user(&request, &id).await?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(&user)))
+}
+
+/// The permissions granted by the role of `user`, none if the role no longer exists.
+async fn role_permissions(state: &AppState, user: &User) -> Result<Permissions, ApiError> {
+    Ok(state
+        .database
+        .find_role(&user.role)
+        .await?
+        .map(|role| role.permissions)
+        .unwrap_or_else(Permissions::empty))
+}
 
-pub async fn delete_user(
+pub async fn update_user(
     request: HttpRequest,
-    data: Form<UserIdRequest>,
-    headers: &Header<AuthorizationHeader>,
-) -> Result<HttpResponse, Error> {
-    if headers.authorization.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    id: Path<String>,
+    data: Form<UserUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let mut permissions = Permissions::empty();
+
+    if data.email.is_some() || data.role.is_some() {
+        permissions |= Permissions::MANAGE_USERS;
     }
 
-    let auth_token = headers.authorization.clone().unwrap();
+    if data.quota.is_some() {
+        permissions |= Permissions::MANAGE_QUOTAS;
+    }
 
-    let state = request.app_data::<AppState>().unwrap();
-    let users = state.database.collection::<User>("users");
-    let files = state.database.collection::<File>("files");
-    let storage = state.storage.clone();
+    let requester = authorize(&request, Scopes::empty(), Permissions::empty()).await?;
+    let own = requester.user._id.to_hex() == *id;
 
-    let requester = users
-        .find_one(doc! {"token": auth_token}, None)
-        .await
-        .unwrap();
+    if data.slug.is_some() && !own {
+        permissions |= Permissions::MANAGE_USERS;
+    }
 
-    if requester.is_none() {
-        return Ok(HttpResponse::Unauthorized().body("Unauthorized"));
+    //? Users may pick their own slug scheme, anything else is up to administrators
+    if !own || !permissions.is_empty() {
+        authorize(&request, Scopes::ADMIN, permissions).await?;
     }
 
-    let requester = requester.unwrap();
+    let state = state(&request)?;
 
-    let user = users
-        .find_one(doc! {"_id": ObjectId::from_str(&data.id).unwrap()}, None)
-        .await
-        .unwrap();
+    let user = find_user(&request, &id).await?;
 
-    if user.is_none() {
-        return Ok(HttpResponse::NotFound().body("Not Found"));
+    if let Some(ref email) = data.email {
+        validate_email(email)?;
     }
 
-    let user = user.unwrap();
+    if let Some(ref role) = data.role {
+        let role = state
+            .database
+            .find_role(role)
+            .await?
+            .ok_or_else(|| ApiError::bad_request("The specified role does not exist"))?;
 
-    if requester._id.to_hex() == user._id.to_hex() {
-        let user_result = users.delete_one(doc! {"_id": user._id}, None).await;
+        //? Nobody can grant a role more powerful than their own, or change the role of a user
+        //? who holds permissions they do not have
+        let current = role_permissions(state, &user).await?;
 
-        if user_result.is_err() {
-            return Ok(HttpResponse::InternalServerError()
-                .body("There was an error deleting the user from the database"));
+        if !requester.permissions.contains(role.permissions | current) {
+            return Err(ApiError::forbidden());
         }
+    }
 
-        let files_result = files.delete_many(doc! {"uploader": user._id}, None).await;
+    if matches!(data.quota, Some(quota) if quota < 0) {
+        return Err(ApiError::bad_request("The quota must not be negative"));
+    }
 
-        if files_result.is_err() {
-            return Ok(HttpResponse::InternalServerError()
-                .body("There was an error deleting the user's files from the database"));
-        }
+    state.database.update_user(user._id, &data).await?;
 
-        match storage {
-            Storage::Local(ref storage) => {
-                let path = format!("{}/{}", storage, user._id.to_hex());
-                match tokio::fs::remove_dir_all(&path).await {
-                    Ok(_) => {
-                        return Ok(HttpResponse::Ok().body("User deleted"));
-                    }
-                    Err(_) => {
-                        return Ok(HttpResponse::InternalServerError()
-                            .body("There was an error deleting the user's storage"));
-                    }
-                }
-            }
-            Storage::S3(ref _storage) => {
-                todo!("S3 storage");
-            }
-        };
-    };
+    let user = find_user(&request, &id).await?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(&user)))
+}
+
+pub async fn suspend_user(
+    request: HttpRequest,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    set_suspended(request, id, true).await
+}
+
+pub async fn unsuspend_user(
+    request: HttpRequest,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    set_suspended(request, id, false).await
+}
 
-    if !requester.privileges.contains(Privileges::ADMIN) {
-        return Ok(HttpResponse::Forbidden().body("Forbidden"));
+async fn set_suspended(
+    request: HttpRequest,
+    id: Path<String>,
+    suspended: bool,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::ADMIN, Permissions::MANAGE_USERS).await?;
+
+    let state = state(&request)?;
+
+    let user = find_user(&request, &id).await?;
+
+    if user._id == requester.user._id {
+        return Err(ApiError::bad_request("You can not suspend yourself"));
     }
 
-    let user_result = users.delete_one(doc! {"_id": user._id}, None).await;
+    //? Nobody can lock out a user who holds permissions they do not have
+    if !requester
+        .permissions
+        .contains(role_permissions(state, &user).await?)
+    {
+        return Err(ApiError::forbidden());
+    }
 
-    if user_result.is_err() {
-        return Ok(HttpResponse::InternalServerError()
-            .body("There was an error deleting the user from the database"));
+    state.database.set_suspended(user._id, suspended).await?;
+
+    match suspended {
+        true => Ok(HttpResponse::Ok().body("User suspended")),
+        false => Ok(HttpResponse::Ok().body("User unsuspended")),
     }
+}
+
+/// Deletes a user, users can delete themselves while user managers can delete anyone who holds
+/// no permissions they lack.
+pub async fn delete_user(request: HttpRequest, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::DELETE, Permissions::empty()).await?;
+
+    let state = state(&request)?;
 
-    let files_result = files.delete_many(doc! {"uploader": user._id}, None).await;
+    let user = find_user(&request, &id).await?;
 
-    if files_result.is_err() {
-        return Ok(HttpResponse::InternalServerError()
-            .body("There was an error deleting the user's files from the database"));
+    if user._id != requester.user._id
+        && !(requester.allows(Scopes::ADMIN, Permissions::MANAGE_USERS)
+            && requester
+                .permissions
+                .contains(role_permissions(state, &user).await?))
+    {
+        return Err(ApiError::forbidden());
     }
 
-    match storage {
-        Storage::Local(ref storage) => {
-            let path = format!("{}/{}", storage, user._id.to_hex());
-            match tokio::fs::remove_dir_all(&path).await {
-                Ok(_) => {
-                    return Ok(HttpResponse::Ok().body("User deleted"));
-                }
-                Err(_) => {
-                    return Ok(HttpResponse::InternalServerError()
-                        .body("There was an error deleting the user's storage"));
-                }
-            }
-        }
-        Storage::S3(ref _storage) => {
-            todo!("S3 storage");
-        }
-    };
+    state.database.delete_user(user._id).await?;
+
+    //? Without accounts left nobody could ever register as the first administrator again
+    if state.database.count_users().await? == 0 {
+        state.database.release_bootstrap().await?;
+    }
+
+    state
+        .storage
+        .delete_prefix(&user_prefix(&user._id.to_hex()))
+        .await?;
+
+    Ok(HttpResponse::Ok().body("User deleted"))
 }
@@ -0,0 +1,267 @@
+use actix_web::{
+    web::{self, Form, Query},
+    HttpRequest, HttpResponse, Result,
+};
+use base64::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{
+    modules::{
+        crypto::open_text,
+        passphrases,
+        passwords::authenticate,
+        vault::{self, KEY_SIZE},
+    },
+    routes::{api::v1::auth::authorize, error::ApiError, state},
+    structs::{
+        files::File,
+        users::User,
+        vault::{Vault, VaultCreateRequest, VaultListRequest, VaultUnlockRequest},
+        Permissions, Scopes,
+    },
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u64 = 25;
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// Checks `password` against the user's own, as every vault operation involving it does.
+async fn verify(state: &AppState, user: &User, password: &str) -> Result<(), ApiError> {
+    match authenticate(
+        state.database.as_ref(),
+        &state.config.passwords,
+        user,
+        password,
+    )
+    .await?
+    {
+        true => Ok(()),
+        false => Err(ApiError::Unauthorized("Invalid password".to_string())),
+    }
+}
+
+pub async fn get_vault(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::READ, Permissions::empty()).await?;
+
+    let vault = requester
+        .user
+        .vault
+        .ok_or_else(|| ApiError::not_found("You have not set up a vault"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "public_key": vault.public_key,
+        "custodial": vault.private_key.is_some(),
+        "created_at": vault.created_at.to_rfc3339(),
+    })))
+}
+
+/// Sets up a vault for the requester, only files uploaded afterwards can be recovered from it.
+///
+/// With a password the server generates the key pair and keeps the private key wrapped with a
+/// key derived from that password. With a public key the user keeps the private key themselves
+/// and opens the sealed links on their own device.
+pub async fn create_vault(
+    request: HttpRequest,
+    data: Form<VaultCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::empty(), Permissions::empty()).await?;
+
+    let state = state(&request)?;
+    let user = requester.user;
+
+    if user.vault.is_some() {
+        return Err(ApiError::Conflict(
+            "You already have a vault, delete it first to replace it".to_string(),
+        ));
+    }
+
+    let vault = match (&data.password, &data.public_key) {
+        (Some(password), None) => {
+            verify(state, &user, password).await?;
+
+            let (private_key, public_key) = vault::generate();
+            let (password, secret, config) = (
+                password.clone(),
+                user._id.bytes(),
+                state.config.passwords.clone(),
+            );
+
+            //? The user's ID binds the wrapped key to this account
+            let private_key =
+                web::block(move || passphrases::lock(&password, &secret, &private_key, &config))
+                    .await?
+                    .map_err(|e| ApiError::internal(format!("vault lock: {}", e)))?;
+
+            Vault {
+                public_key: base64::encode(public_key),
+                private_key: Some(private_key),
+                created_at: Utc::now(),
+            }
+        }
+        (None, Some(public_key)) => match base64::decode(public_key) {
+            Ok(decoded) if decoded.len() == KEY_SIZE => Vault {
+                public_key: base64::encode(decoded),
+                private_key: None,
+                created_at: Utc::now(),
+            },
+            _ => {
+                return Err(ApiError::bad_request(
+                    "The public key must be 32 bytes of base64",
+                ));
+            }
+        },
+        _ => {
+            return Err(ApiError::bad_request(
+                "Exactly one of password and public_key must be given",
+            ));
+        }
+    };
+
+    //? Guards against a concurrent request setting up a vault in the meantime
+    if !state.database.create_vault(user._id, &vault).await? {
+        return Err(ApiError::Conflict(
+            "You already have a vault, delete it first to replace it".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::Created().json(json!({
+        "public_key": vault.public_key,
+        "custodial": vault.private_key.is_some(),
+        "created_at": vault.created_at.to_rfc3339(),
+    })))
+}
+
+/// Deletes the requester's vault, the links it held can no longer be recovered.
+pub async fn delete_vault(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::DELETE, Permissions::empty()).await?;
+
+    let state = state(&request)?;
+
+    if requester.user.vault.is_none() {
+        return Err(ApiError::not_found("You have not set up a vault"));
+    }
+
+    state.database.delete_vault(requester.user._id).await?;
+
+    Ok(HttpResponse::Ok().body("Vault deleted"))
+}
+
+/// Lists the requester's recoverable files with their links still sealed, for users who hold
+/// their own private key.
+pub async fn list_vault_files(
+    request: HttpRequest,
+    query: Query<VaultListRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::READ, Permissions::empty()).await?;
+
+    vault_files(&request, &requester.user, &query, None).await
+}
+
+/// Lists the requester's recoverable files with working links, opening them with the private
+/// key the server keeps for them.
+pub async fn unlock_vault_files(
+    request: HttpRequest,
+    query: Query<VaultListRequest>,
+    data: Form<VaultUnlockRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let requester = authorize(&request, Scopes::READ, Permissions::empty()).await?;
+
+    let state = state(&request)?;
+    let user = requester.user;
+
+    let lock = match user.vault {
+        Some(Vault {
+            private_key: Some(ref lock),
+            ..
+        }) => lock.clone(),
+        Some(_) => {
+            return Err(ApiError::bad_request(
+                "Your vault's private key is not kept on the server",
+            ));
+        }
+        None => return Err(ApiError::not_found("You have not set up a vault")),
+    };
+
+    verify(state, &user, &data.password).await?;
+
+    let (password, secret) = (data.password.clone(), user._id.bytes());
+    let private_key = web::block(move || passphrases::unlock(&password, &secret, &lock))
+        .await?
+        .ok_or_else(|| ApiError::internal("the vault private key could not be unwrapped"))?;
+
+    vault_files(&request, &user, &query, Some(&private_key)).await
+}
+
+async fn vault_files(
+    request: &HttpRequest,
+    user: &User,
+    query: &VaultListRequest,
+    private_key: Option<&[u8]>,
+) -> Result<HttpResponse, ApiError> {
+    let state = state(request)?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let (found, total) = state
+        .database
+        .list_vault_files(
+            user._id,
+            page.saturating_sub(1).saturating_mul(per_page),
+            per_page,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "files": found.iter().map(|file| describe(file, private_key)).collect::<Vec<_>>(),
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    })))
+}
+
+fn describe(file: &File, private_key: Option<&[u8]>) -> Value {
+    let mut description = json!({
+        "id": file.public_id(),
+        "size": file.size,
+        "protected": file.lock.is_some(),
+        "created_at": file.created_at.to_rfc3339(),
+        "expires_at": file.expires_at.map(|date| date.to_chrono().to_rfc3339()),
+        "max_downloads": file.max_downloads,
+        "downloads": file.downloads,
+    });
+
+    let sealed = file.vault_key.as_deref().unwrap_or_default();
+
+    let opened = match private_key.and_then(|private_key| vault::open(private_key, sealed)) {
+        Some(opened) if opened.len() == KEY_SIZE + 12 => opened,
+        _ => {
+            description["sealed_key"] = json!(sealed);
+
+            if !file.sealed {
+                description["filename"] = json!(file.filename);
+            }
+
+            return description;
+        }
+    };
+
+    let (key, nonce) = opened.split_at(KEY_SIZE);
+
+    //? Sealed metadata of a passphrase-protected file still needs the passphrase
+    let filename = match (file.sealed, &file.lock) {
+        (false, _) => Some(file.filename.clone()),
+        (true, None) => open_text(key, &file.filename),
+        (true, Some(_)) => None,
+    };
+
+    description["filename"] = json!(filename);
+    description["key"] = json!(base64::encode_config(key, URL_SAFE_NO_PAD));
+    description["nonce"] = json!(base64::encode_config(nonce, URL_SAFE_NO_PAD));
+
+    description
+}
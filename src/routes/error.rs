@@ -0,0 +1,225 @@
+//! The error type returned by every handler, rendered as a JSON body of the form
+//! `{"error": {"code", "message", "request_id", "fields"}}`.
+
+use std::fmt;
+
+use actix_web::{
+    error::BlockingError,
+    http::{
+        header::{ContentRange, ContentRangeSpec},
+        StatusCode,
+    },
+    HttpResponse, ResponseError,
+};
+use log::{debug, error};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::modules::{database::DatabaseError, storage::StorageError, validation::FieldError};
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Gone(String),
+    Conflict(String),
+    Taken(FieldError), //? A unique field such as the username is already in use
+    PayloadTooLarge(String),
+    RangeNotSatisfiable(u64), //? Carries the size of the file
+    Invalid(Vec<FieldError>),
+    Internal(String), //? Logged, but never shown to the client
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+    request_id: String,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    fields: &'a [FieldError],
+}
+
+impl ApiError {
+    pub fn bad_request<T: Into<String>>(message: T) -> ApiError {
+        ApiError::BadRequest(message.into())
+    }
+
+    pub fn unauthorized() -> ApiError {
+        ApiError::Unauthorized("A valid token is required".to_string())
+    }
+
+    pub fn forbidden() -> ApiError {
+        ApiError::Forbidden("You do not have permission to do this".to_string())
+    }
+
+    pub fn not_found<T: Into<String>>(message: T) -> ApiError {
+        ApiError::NotFound(message.into())
+    }
+
+    pub fn internal<T: Into<String>>(cause: T) -> ApiError {
+        ApiError::Internal(cause.into())
+    }
+
+    /// A machine readable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Gone(_) => "gone",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Taken(_) => "already_taken",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            ApiError::Invalid(_) => "invalid_fields",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Gone(message)
+            | ApiError::Conflict(message)
+            | ApiError::PayloadTooLarge(message) => message,
+            ApiError::Taken(_) => "The value is already taken",
+            ApiError::RangeNotSatisfiable(_) => "None of the requested ranges can be satisfied",
+            ApiError::Invalid(_) => "One or more fields are invalid",
+            ApiError::Internal(_) => "Internal Server Error",
+        }
+    }
+
+    fn fields(&self) -> &[FieldError] {
+        match self {
+            ApiError::Taken(field) => std::slice::from_ref(field),
+            ApiError::Invalid(fields) => fields,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Internal(cause) => write!(f, "{}: {}", self.code(), cause),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::Conflict(_) | ApiError::Taken(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        //? The request id ties a response to the log line with the underlying cause
+        let request_id = Uuid::new_v4().simple().to_string();
+
+        match self {
+            ApiError::Internal(_) => error!("[{}] {}", request_id, self),
+            _ => debug!("[{}] {}", request_id, self),
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+        response.insert_header(("X-Request-Id", request_id.as_str()));
+
+        if let ApiError::RangeNotSatisfiable(size) = *self {
+            response.insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(size),
+            }));
+        }
+
+        response.json(serde_json::json!({
+            "error": ErrorBody {
+                code: self.code(),
+                message: self.message(),
+                request_id,
+                fields: self.fields(),
+            }
+        }))
+    }
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::Taken(field) => {
+                ApiError::Taken(FieldError::new(field, "is already taken"))
+            }
+            DatabaseError::Backend(e) => ApiError::Internal(format!("database: {}", e)),
+        }
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(e: StorageError) -> Self {
+        ApiError::Internal(format!("storage: {}", e))
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Internal(format!("io: {}", e))
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(e: BlockingError) -> Self {
+        ApiError::Internal(format!("blocking task: {}", e))
+    }
+}
+
+impl From<tera::Error> for ApiError {
+    fn from(e: tera::Error) -> Self {
+        ApiError::Internal(format!("template: {}", e))
+    }
+}
+
+impl From<FieldError> for ApiError {
+    fn from(e: FieldError) -> Self {
+        ApiError::Invalid(vec![e])
+    }
+}
+
+#[tokio::test]
+async fn test_error_response() {
+    let error = ApiError::Taken(FieldError::new("username", "is already taken"));
+    let response = error.error_response();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert!(response.headers().contains_key("X-Request-Id"));
+
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(body["error"]["code"], "already_taken");
+    assert_eq!(body["error"]["fields"][0]["field"], "username");
+
+    let response = ApiError::internal("secret cause").error_response();
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+
+    assert!(!String::from_utf8_lossy(&body).contains("secret cause"));
+}
@@ -1,2 +1,15 @@
 pub mod api;
+pub mod error;
 pub mod views;
+
+use actix_web::HttpRequest;
+
+use crate::AppState;
+use error::ApiError;
+
+/// The shared application state registered on startup.
+pub fn state(request: &HttpRequest) -> Result<&AppState, ApiError> {
+    request
+        .app_data::<AppState>()
+        .ok_or_else(|| ApiError::internal("the application state is not registered"))
+}
@@ -1,33 +1,19 @@
-use actix_web::{Error, HttpRequest, HttpResponse, Result};
-use futures_util::StreamExt;
+use actix_web::{HttpRequest, HttpResponse, Result};
 
-use crate::{structs::files::File, AppState};
+use crate::routes::{error::ApiError, state};
 use tera::Context;
 
-pub async fn index(request: HttpRequest) -> Result<HttpResponse, Error> {
-    let state = request.app_data::<AppState>().unwrap();
+pub async fn index(request: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
 
-    let mut total_size: i64 = 0;
-    let mut total_files: i64 = 0;
-
-    {
-        let files = state.database.collection::<File>("files");
-        let mut cursor = files.find(None, None).await.unwrap();
-
-        while let Some(file) = cursor.next().await {
-            let file = file.unwrap();
-
-            total_size += file.size;
-            total_files += 1;
-        }
-    }
+    let (total_files, total_size) = state.database.file_totals().await?;
 
     let mut context = Context::new();
     context.insert("total_size", &total_size);
     context.insert("total_files", &total_files);
     context.insert("version", env!("CARGO_PKG_VERSION"));
 
-    let html = state.tera.render("index.html", &context).unwrap();
+    let html = state.tera.render("index.html", &context)?;
 
     Ok(HttpResponse::Ok().content_type("text/html").body(html))
 }
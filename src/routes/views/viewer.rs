@@ -0,0 +1,43 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web::Path,
+    HttpRequest, HttpResponse, Result,
+};
+use serde_json::json;
+use tera::Context;
+
+use crate::{
+    modules::container::Compression,
+    routes::{api::v1::files::find_available, error::ApiError, state},
+};
+
+/// Serves the page behind `/v/{id}#key.nonce` links, which downloads the encrypted file and
+/// decrypts it in the browser. The key stays in the URL fragment, which browsers never send.
+pub async fn view_file(request: HttpRequest, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    let state = state(&request)?;
+    let file = find_available(state, &id).await?;
+
+    //? Sealed metadata is handed over encrypted and opened by the page with the key
+    let description = json!({
+        "raw": format!("/api/v1/files/{}/raw", file.public_id()),
+        "size": file.size,
+        "filename": file.filename,
+        "mimetype": file.mimetype,
+        "sealed": file.sealed,
+        "protected": file.lock.is_some(),
+        "limited": file.max_downloads.is_some(),
+        "compressed": file.compression != Compression::None,
+    });
+
+    let mut context = Context::new();
+    context.insert("file", &description.to_string());
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+
+    let html = state.tera.render("viewer.html", &context)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header(("Referrer-Policy", "no-referrer"))
+        .content_type("text/html")
+        .body(html))
+}
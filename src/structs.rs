@@ -1,30 +1,95 @@
-use bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::modules::hashing::hash_string;
+use crate::modules::{
+    container::Compression, hashing::hash_string, roles::DEFAULT_ROLE, slugs::Slug,
+    storage::object_key,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthorizationHeader {
     pub authorization: Option<String>,
 }
 
+/// Implements parsing from and formatting to comma separated names for a bitflags type.
+macro_rules! named_flags {
+    ($flags:ident { $($name:literal => $flag:ident),* $(,)? }) => {
+        impl $flags {
+            const NAMES: &'static [(&'static str, $flags)] = &[$(($name, $flags::$flag)),*];
+
+            /// Parses a comma separated list of names such as `upload,read`.
+            pub fn from_names(names: &str) -> Option<$flags> {
+                names
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .try_fold($flags::empty(), |flags, name| {
+                        $flags::NAMES
+                            .iter()
+                            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                            .map(|(_, flag)| flags | *flag)
+                    })
+            }
+
+            pub fn names(&self) -> Vec<&'static str> {
+                $flags::NAMES
+                    .iter()
+                    .filter(|(_, flag)| self.contains(*flag))
+                    .map(|(name, _)| *name)
+                    .collect()
+            }
+        }
+    };
+}
+
 bitflags::bitflags! {
 
+    /// What a user is allowed to do, granted through their role.
     #[derive(Serialize, Deserialize)]
-    pub struct Privileges: u32 {
-        const ADMIN = 1;
-        const USER = 2;
+    pub struct Permissions: u32 {
+        const UPLOAD = 1;
+        const DELETE_OWN = 2;
+        const DELETE_ANY = 4;
+        const VIEW_STATS = 8;
+        const MANAGE_USERS = 16;
+        const MANAGE_QUOTAS = 32;
+        const MODERATE = 64;
     }
 }
 
-impl Default for Privileges {
-    fn default() -> Self {
-        Privileges::USER
+named_flags!(Permissions {
+    "upload" => UPLOAD,
+    "delete_own" => DELETE_OWN,
+    "delete_any" => DELETE_ANY,
+    "view_stats" => VIEW_STATS,
+    "manage_users" => MANAGE_USERS,
+    "manage_quotas" => MANAGE_QUOTAS,
+    "moderate" => MODERATE,
+});
+
+bitflags::bitflags! {
+
+    /// What an API token may be used for.
+    #[derive(Serialize, Deserialize)]
+    pub struct Scopes: u32 {
+        const UPLOAD = 1;
+        const READ = 2;
+        const DELETE = 4;
+        const ADMIN = 8;
+        const TOKENS = 16; //? Creating tokens and revoking ones other than the one in use
     }
 }
 
+named_flags!(Scopes {
+    "upload" => UPLOAD,
+    "read" => READ,
+    "delete" => DELETE,
+    "admin" => ADMIN,
+    "tokens" => TOKENS,
+});
+
 pub mod users {
     use super::*;
 
@@ -33,10 +98,16 @@ pub mod users {
         pub _id: ObjectId,
         pub username: String,
         pub email: String,
-        pub password: String, //? SHA3-512 hash
+        pub password: String, //? Argon2id PHC string, or a legacy SHA3-512 hash
         pub quota: UserQuota,
-        pub privileges: Privileges,
-        pub token: String, //? SHA3-512 hash
+        pub role: String,  //? Name of the role granting the user their permissions
+        pub token: String, //? SHA3-512 hash of the primary token, which has every scope
+        #[serde(default)]
+        pub suspended: bool,
+        #[serde(default)]
+        pub vault: Option<vault::Vault>,
+        #[serde(default)]
+        pub slug: Option<Slug>, //? Overrides the server's slug scheme for the user's uploads
         #[serde(with = "chrono_datetime_as_bson_datetime")]
         pub created_at: DateTime<Utc>,
         #[serde(with = "chrono_datetime_as_bson_datetime")]
@@ -44,6 +115,7 @@ pub mod users {
     }
 
     impl User {
+        /// Creates a new user, `password` must already be hashed with [`crate::modules::passwords::hash_password`].
         pub fn from<T>(username: T, password: T, email: T, token: T) -> User
         where
             T: Into<String>,
@@ -51,11 +123,14 @@ pub mod users {
             User {
                 _id: ObjectId::new(),
                 username: username.into(),
-                password: hash_string(password.into()),
+                password: password.into(),
                 email: email.into(),
                 quota: User::default_quota(),
                 token: hash_string(token.into()),
-                privileges: Privileges::default(),
+                role: DEFAULT_ROLE.to_string(),
+                suspended: false,
+                vault: None,
+                slug: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             }
@@ -85,10 +160,204 @@ pub mod users {
         pub username: String,
         pub password: String,
         pub email: String,
+        pub invite: Option<String>, //? Required when registration is invite-only
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct LoginRequest {
+        pub username: String, //? Username or email address
+        pub password: String,
+        pub name: Option<String>, //? Name of the issued token
     }
 
-    pub struct UserIdRequest {
+    #[derive(Debug, Deserialize)]
+    pub struct UserListRequest {
+        pub page: Option<u64>,
+        pub per_page: Option<u64>,
+        pub username: Option<String>, //? Case insensitive substring
+        pub email: Option<String>,    //? Case insensitive substring
+        pub suspended: Option<bool>,
+        pub role: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct UserUpdateRequest {
+        pub email: Option<String>,
+        pub role: Option<String>,
+        pub quota: Option<i64>, //? Available bytes
+        pub slug: Option<Slug>,
+    }
+
+    /// The parts of a [`User`] that are safe to hand out, without any password or token hashes.
+    #[derive(Debug, Serialize)]
+    pub struct UserResponse<'a> {
         pub id: String,
+        pub username: &'a str,
+        pub email: &'a str,
+        pub quota: &'a UserQuota,
+        pub role: &'a str,
+        pub suspended: bool,
+        pub vault: bool,
+        pub slug: Option<Slug>,
+        pub created_at: String,
+        pub updated_at: String,
+    }
+
+    impl<'a> From<&'a User> for UserResponse<'a> {
+        fn from(user: &'a User) -> Self {
+            UserResponse {
+                id: user._id.to_hex(),
+                username: &user.username,
+                email: &user.email,
+                quota: &user.quota,
+                role: &user.role,
+                suspended: user.suspended,
+                vault: user.vault.is_some(),
+                slug: user.slug,
+                created_at: user.created_at.to_rfc3339(),
+                updated_at: user.updated_at.to_rfc3339(),
+            }
+        }
+    }
+}
+
+pub mod roles {
+    use super::*;
+
+    /// A named set of permissions, `_id` is the role's name.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Role {
+        pub _id: String,
+        pub permissions: Permissions,
+        #[serde(default)]
+        pub max_file_lifetime: Option<i64>, //? Seconds, `None` allows files that never expire
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        pub created_at: DateTime<Utc>,
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        pub updated_at: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct RoleRequest {
+        pub permissions: String, //? Comma separated, e.g. `upload,delete_own`
+        pub max_file_lifetime: Option<i64>, //? Seconds
+    }
+}
+
+pub mod invites {
+    use super::*;
+
+    /// An invite code for registering while registration is invite-only, only its SHA3-512
+    /// hash is stored.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Invite {
+        pub _id: ObjectId,
+        pub hash: String,
+        pub created_by: ObjectId,
+        pub uses: i64,
+        pub max_uses: Option<i64>, //? `None` for unlimited uses
+        pub role: Option<String>,  //? Role given to invited users instead of the default
+        pub quota: Option<i64>,    //? Available bytes given to invited users instead of the default
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        pub created_at: DateTime<Utc>,
+        pub expires_at: Option<bson::DateTime>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct InviteCreateRequest {
+        pub max_uses: Option<i64>,
+        pub expires_in: Option<i64>, //? Seconds
+        pub role: Option<String>,
+        pub quota: Option<i64>,
+    }
+}
+
+pub mod tokens {
+    use super::*;
+
+    /// A named API token, only the SHA3-512 hash of the secret is stored.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ApiToken {
+        pub _id: ObjectId,
+        pub user: ObjectId,
+        pub name: String,
+        pub hash: String,
+        pub scopes: Scopes,
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        pub created_at: DateTime<Utc>,
+        pub last_used_at: Option<bson::DateTime>,
+        pub expires_at: Option<bson::DateTime>,
+    }
+
+    impl ApiToken {
+        /// Creates a token for `user`, returning it together with its secret.
+        pub fn new<T: Into<String>>(
+            user: ObjectId,
+            name: T,
+            scopes: Scopes,
+            expires_at: Option<DateTime<Utc>>,
+        ) -> (ApiToken, String) {
+            let secret = users::User::generate_token();
+
+            let token = ApiToken {
+                _id: ObjectId::new(),
+                user,
+                name: name.into(),
+                hash: hash_string(&secret),
+                scopes,
+                created_at: Utc::now(),
+                last_used_at: None,
+                expires_at: expires_at.map(bson::DateTime::from_chrono),
+            };
+
+            (token, secret)
+        }
+
+        pub fn is_expired(&self) -> bool {
+            match self.expires_at {
+                Some(expires_at) => expires_at.to_chrono() <= Utc::now(),
+                None => false,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct TokenCreateRequest {
+        pub name: String,
+        pub scopes: String,          //? Comma separated, e.g. `upload,read`
+        pub expires_in: Option<i64>, //? Seconds
+    }
+}
+
+pub mod vault {
+    use super::files::PassphraseLock;
+    use super::*;
+
+    /// A user's key pair for recovering the links of their uploads, see `modules::vault`.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Vault {
+        pub public_key: String,
+        pub private_key: Option<PassphraseLock>, //? Wrapped with the password, `None` if held by the user
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        pub created_at: DateTime<Utc>,
+    }
+
+    /// Either a password to keep the private key on the server, or the user's own public key.
+    #[derive(Debug, Deserialize)]
+    pub struct VaultCreateRequest {
+        pub password: Option<String>,
+        pub public_key: Option<String>, //? Base64
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct VaultUnlockRequest {
+        pub password: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct VaultListRequest {
+        pub page: Option<u64>,
+        pub per_page: Option<u64>,
     }
 }
 
@@ -98,14 +367,83 @@ pub mod files {
     #[derive(Debug, Serialize, Deserialize)]
     pub struct File {
         pub _id: ObjectId,
+        #[serde(default)]
+        pub id: Option<String>, //? Public ID used in URLs, `None` for files addressed by their hash
         pub filename: String,
         pub mimetype: String,
         pub uploader: ObjectId,
         pub hash: String,
         pub dkey: String,
         pub size: i64,
+        #[serde(default)]
+        pub blob: Option<String>, //? Storage key, `None` for files stored under their hash
         #[serde(with = "chrono_datetime_as_bson_datetime")]
         pub created_at: DateTime<Utc>,
+        #[serde(default)]
+        pub expires_at: Option<bson::DateTime>,
+        #[serde(default)]
+        pub purged: bool, //? The blob is gone, the record only remains to answer with 410 Gone
+        #[serde(default)]
+        pub max_downloads: Option<i64>, //? 1 burns the file after it has been read once
+        #[serde(default)]
+        pub downloads: i64,
+        #[serde(default)]
+        pub lock: Option<PassphraseLock>, //? Set when the file is protected by a passphrase
+        #[serde(default)]
+        pub sealed: bool, //? `filename` and `mimetype` are encrypted with the file key
+        #[serde(default)]
+        pub vault_key: Option<String>, //? Link key and nonce sealed to the uploader's vault
+        #[serde(default)]
+        pub compression: Compression, //? Applied before encryption, `size` is still the original size
+    }
+
+    /// The file key wrapped with a key derived from a passphrase, see `modules::passphrases`.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PassphraseLock {
+        pub salt: String,
+        pub nonce: String,
+        pub key: String, //? Wrapped file key
+        pub memory_cost: u32,
+        pub time_cost: u32,
+        pub parallelism: u32,
+    }
+
+    impl File {
+        /// Matches a file by its public ID, or by its hash for files uploaded before public IDs existed.
+        pub fn lookup(id: &str) -> Document {
+            doc! {
+                "$or": [
+                    {"id": id},
+                    {"hash": id, "id": null},
+                ]
+            }
+        }
+
+        /// The identifier used in links to this file.
+        pub fn public_id(&self) -> &str {
+            self.id.as_deref().unwrap_or(&self.hash)
+        }
+
+        pub fn is_exhausted(&self) -> bool {
+            match self.max_downloads {
+                Some(max_downloads) => self.downloads >= max_downloads,
+                None => false,
+            }
+        }
+
+        pub fn is_expired(&self) -> bool {
+            match self.expires_at {
+                Some(expires_at) => expires_at.to_chrono() <= Utc::now(),
+                None => false,
+            }
+        }
+
+        pub fn storage_key(&self) -> String {
+            match self.blob {
+                Some(ref blob) => blob.clone(),
+                None => object_key(&self.uploader.to_hex(), &self.hash),
+            }
+        }
     }
 
     #[derive(Debug, Deserialize)]
@@ -114,9 +452,48 @@ pub mod files {
         pub nonce: String,
     }
 
+    #[derive(Debug, Deserialize)]
+    pub struct FileUnlockRequest {
+        pub passphrase: String,
+    }
+
+    /// Options for `POST /api/v1/files`, passed in the query string since the body is streamed.
+    #[derive(Debug, Deserialize)]
+    pub struct FileUploadRequest {
+        pub expires_in: Option<i64>, //? Seconds
+        pub expires_at: Option<DateTime<Utc>>,
+        pub max_downloads: Option<i64>,
+        pub slug: Option<Slug>,
+        #[serde(default)]
+        pub fragment: bool, //? Shared as a `/v/{id}#key.nonce` link, whose viewer can not decompress
+    }
+
     #[derive(Debug, Serialize, Deserialize)]
     pub struct FileDeleteRequest {
-        pub hash: String,
-        pub dkey: String,
+        #[serde(alias = "hash")] //? Clients written before public IDs still send `hash`
+        pub id: String,
+        pub dkey: Option<String>, //? Not needed when deleting with a token
     }
 }
+
+#[test]
+fn test_named_flags() {
+    assert_eq!(
+        Scopes::from_names("upload, READ"),
+        Some(Scopes::UPLOAD | Scopes::READ)
+    );
+    assert_eq!(Scopes::from_names(""), Some(Scopes::empty()));
+    assert_eq!(Scopes::from_names("upload,fly"), None);
+    assert_eq!(
+        Scopes::all().names(),
+        vec!["upload", "read", "delete", "admin", "tokens"]
+    );
+}
+
+#[test]
+fn test_delete_request_hash() {
+    let request: files::FileDeleteRequest =
+        serde_json::from_str(r#"{"hash": "abcd1234", "dkey": null}"#).unwrap();
+
+    assert_eq!(request.id, "abcd1234");
+}